@@ -1,18 +1,94 @@
-use anyhow::Result;
-use ssh2::Session;
-use std::io::Read;
+use crate::config::{AppConfig, SshConfig};
+use crate::terminal;
+use anyhow::{anyhow, Result};
+use homedir::my_home;
+use ssh2::{Channel, ErrorCode, ExtendedData, Session};
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use tracing::info;
 
-pub fn connect_ssh(user: &str, host: &str) -> Result<Session> {
+const LIBSSH2_ERROR_EAGAIN: libc::c_int = -37;
+
+const LIBSSH2_ERROR_FILE: libc::c_int = -16;
+
+/// Key files tried when neither the agent nor `ssh.identity_file` is available.
+const DEFAULT_IDENTITY_FILES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+pub fn connect_ssh(user: &str, host: &str, options: &SshConfig) -> Result<Session> {
     info!("Connecting to SSH server...");
-    let tcp = std::net::TcpStream::connect(format!("{}:22", host))?;
+    let tcp = std::net::TcpStream::connect((host, options.port()))?;
     let mut session = Session::new()?;
     session.set_tcp_stream(tcp);
     session.handshake()?;
-    session.userauth_agent(user)?;
+    authenticate(&session, user, options)?;
     Ok(session)
 }
 
+/// Connects to the app's server as the configured deploy user.
+pub fn connect_app(config: &AppConfig) -> Result<Session> {
+    let options = config.ssh();
+    connect_ssh(options.user(), &config.server, &options)
+}
+
+/// Tries the SSH agent first, then the configured identity file or the
+/// default keys in `~/.ssh`, asking for a passphrase when a key needs one.
+fn authenticate(session: &Session, user: &str, options: &SshConfig) -> Result<()> {
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+
+    let home = my_home()?.ok_or_else(|| anyhow!("Could not find home directory"))?;
+    let identity_files: Vec<PathBuf> = match &options.identity_file {
+        Some(path) => vec![match path.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None => PathBuf::from(path),
+        }],
+        None => DEFAULT_IDENTITY_FILES
+            .iter()
+            .map(|name| home.join(".ssh").join(name))
+            .filter(|path| path.exists())
+            .collect(),
+    };
+
+    for identity_file in &identity_files {
+        match session.userauth_pubkey_file(user, None, identity_file, None) {
+            Ok(()) if session.authenticated() => return Ok(()),
+            // libssh2 reports an unreadable key file when it is encrypted.
+            Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_FILE) => {
+                if !terminal::stdin_is_tty() {
+                    continue;
+                }
+                let passphrase = terminal::prompt_hidden(&format!(
+                    "Enter passphrase for {}: ",
+                    identity_file.display()
+                ))?;
+                session.userauth_pubkey_file(user, None, identity_file, Some(&passphrase))?;
+                if session.authenticated() {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let tried: Vec<String> = identity_files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    Err(anyhow!(
+        "SSH authentication as {} failed, tried the SSH agent{}",
+        user,
+        if tried.is_empty() {
+            String::new()
+        } else {
+            format!(" and {}", tried.join(", "))
+        }
+    ))
+}
+
 pub fn run_ssh_commands(session: &Session, commands: &[&str]) -> Result<()> {
     for cmd in commands {
         let mut channel = session.channel_session()?;
@@ -33,7 +109,7 @@ pub fn run_ssh_commands(session: &Session, commands: &[&str]) -> Result<()> {
         if exit_status != 0 {
             anyhow::bail!(
                 "Command failed: {}\nOutput: {}\nError: {}",
-                cmd,
+                redact_secrets(cmd),
                 output,
                 stderr
             );
@@ -41,3 +117,255 @@ pub fn run_ssh_commands(session: &Session, commands: &[&str]) -> Result<()> {
     }
     Ok(())
 }
+
+pub fn run_ssh_command_output(session: &Session, cmd: &str) -> Result<String> {
+    run_ssh_command_with_input(session, cmd, &[])
+}
+
+/// Like [`run_ssh_command_output`], but writes `input` to the command's stdin.
+/// Used to hand secrets to remote commands without putting them on the command line.
+pub fn run_ssh_command_with_input(session: &Session, cmd: &str, input: &[u8]) -> Result<String> {
+    run_ssh_command_with_writer(session, cmd, &mut |stdin| {
+        if !input.is_empty() {
+            stdin.write_all(input)?;
+        }
+        Ok(())
+    })
+}
+
+/// Like [`run_ssh_command_with_input`], but lets `write` produce the command's
+/// stdin incrementally, so large inputs never have to be held in memory.
+pub fn run_ssh_command_with_writer(
+    session: &Session,
+    cmd: &str,
+    write: &mut dyn FnMut(&mut dyn Write) -> Result<()>,
+) -> Result<String> {
+    let mut channel = session.channel_session()?;
+    channel.exec(cmd)?;
+    // EOF is sent even after a failed write so the remote command does not
+    // wait for more input. If it exited early instead, its error output
+    // explains the failure better than the broken pipe does.
+    let written = write(&mut channel);
+    let eof = channel.send_eof();
+    if written.is_ok() {
+        eof?;
+    }
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+
+    let mut stderr = String::new();
+    channel.stderr().read_to_string(&mut stderr)?;
+
+    channel.wait_close()?;
+
+    let exit_status = channel.exit_status()?;
+    if exit_status != 0 {
+        anyhow::bail!(
+            "Command failed: {}\nOutput: {}\nError: {}",
+            redact_secrets(cmd),
+            output,
+            stderr
+        );
+    }
+    written?;
+    Ok(output)
+}
+
+/// Runs a command and copies its output to the terminal as it arrives.
+/// Stderr is merged into stdout so both streams keep their relative order.
+pub fn stream_ssh_command(session: &Session, cmd: &str) -> Result<i32> {
+    let mut channel = session.channel_session()?;
+    channel.handle_extended_data(ExtendedData::Merge)?;
+    channel.exec(cmd)?;
+
+    let mut stdout = std::io::stdout();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = channel.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        stdout.write_all(&buf[..n])?;
+        stdout.flush()?;
+    }
+
+    channel.wait_close()?;
+    Ok(channel.exit_status()?)
+}
+
+/// Runs a command with the local terminal attached: stdin is forwarded, output
+/// is written as it arrives and, when `tty` is set, a PTY is requested and kept
+/// in sync with the local window size. Returns the remote exit code.
+pub fn run_interactive_ssh_command(session: &Session, cmd: &str, tty: bool) -> Result<i32> {
+    let mut channel = session.channel_session()?;
+
+    let mut size = terminal::window_size();
+    let _raw_mode = if tty {
+        let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string());
+        let dim = size.map(|(cols, rows)| (cols, rows, 0, 0));
+        channel.request_pty(&term, None, dim)?;
+        Some(terminal::RawMode::enable()?)
+    } else {
+        None
+    };
+    channel.exec(cmd)?;
+
+    // Stdin is read on its own thread because it blocks, the session is
+    // switched to non-blocking so one loop can service both directions.
+    let (stdin_tx, stdin_rx) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => {
+                    let _ = stdin_tx.send(Vec::new());
+                    break;
+                }
+                Ok(n) => {
+                    if stdin_tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    session.set_blocking(false);
+    let result = pump_interactive_channel(&mut channel, &stdin_rx, &mut size, tty);
+    session.set_blocking(true);
+    result?;
+
+    // Output that was buffered when the remote side signalled EOF.
+    std::io::copy(&mut channel, &mut std::io::stdout())?;
+    std::io::copy(&mut channel.stderr(), &mut std::io::stderr())?;
+
+    channel.wait_close()?;
+    Ok(channel.exit_status()?)
+}
+
+fn pump_interactive_channel(
+    channel: &mut Channel,
+    stdin_rx: &mpsc::Receiver<Vec<u8>>,
+    size: &mut Option<(u32, u32)>,
+    tty: bool,
+) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    let mut stderr = std::io::stderr();
+    let mut buf = [0u8; 8192];
+    let mut stdin_open = true;
+
+    while !channel.eof() {
+        let mut idle = true;
+
+        match channel.read(&mut buf) {
+            Ok(0) => {}
+            Ok(n) => {
+                stdout.write_all(&buf[..n])?;
+                stdout.flush()?;
+                idle = false;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        match channel.stderr().read(&mut buf) {
+            Ok(0) => {}
+            Ok(n) => {
+                stderr.write_all(&buf[..n])?;
+                stderr.flush()?;
+                idle = false;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        while stdin_open {
+            match stdin_rx.try_recv() {
+                Ok(data) if data.is_empty() => {
+                    retry_would_block(|| channel.send_eof())?;
+                    stdin_open = false;
+                }
+                Ok(data) => {
+                    write_all_non_blocking(channel, &data)?;
+                    idle = false;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => stdin_open = false,
+            }
+        }
+
+        if tty {
+            let current = terminal::window_size();
+            if current != *size {
+                if let Some((cols, rows)) = current {
+                    retry_would_block(|| channel.request_pty_size(cols, rows, None, None))?;
+                }
+                *size = current;
+            }
+        }
+
+        if idle {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    Ok(())
+}
+
+fn write_all_non_blocking(channel: &mut Channel, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        match channel.write(data) {
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+fn retry_would_block(mut f: impl FnMut() -> Result<(), ssh2::Error>) -> Result<()> {
+    loop {
+        match f() {
+            Ok(()) => return Ok(()),
+            Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Masks the age secret key so it does not end up in terminal output or logs.
+pub fn redact_secrets(cmd: &str) -> String {
+    const MARKER: &str = "SOPS_AGE_KEY=";
+    match cmd.find(MARKER) {
+        Some(start) => {
+            let value_start = start + MARKER.len();
+            let value_end = cmd[value_start..]
+                .find(char::is_whitespace)
+                .map_or(cmd.len(), |end| value_start + end);
+            format!("{}***{}", &cmd[..value_start], &cmd[value_end..])
+        }
+        None => cmd.to_string(),
+    }
+}
+
+/// Quotes a value for use as a single word in a remote shell command.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_age_key() {
+        assert_eq!(
+            redact_secrets("cd app && export SOPS_AGE_KEY=AGE-SECRET-KEY-1ABC && sops exec-env"),
+            "cd app && export SOPS_AGE_KEY=*** && sops exec-env"
+        );
+        assert_eq!(redact_secrets("docker ps"), "docker ps");
+    }
+}
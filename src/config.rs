@@ -1,7 +1,16 @@
 use anyhow::{anyhow, Result};
 use homedir::my_home;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::jobs;
+use crate::overrides::ComposeOverride;
+use crate::volumes::VolumeConfig;
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct AppConfig {
@@ -10,8 +19,391 @@ pub struct AppConfig {
     pub url: String,
     pub port: u16,
     pub env: Option<EnvConfig>,
-    pub volumes: Option<Vec<String>>,
+    pub volumes: Option<Vec<VolumeConfig>>,
     pub version: String,
+    /// Extra processes run from the same image, e.g. a background worker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<BTreeMap<String, ProcessConfig>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<SshConfig>,
+    /// CPU architecture of the server as reported by `dpkg --print-architecture`.
+    /// Detected by setup, or on the first deploy of older configs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Overrides the platform the image is built for, e.g. `linux/amd64`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Distributes images through a registry instead of uploading archives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<RegistryConfig>,
+    /// Health check of the main process, deploys wait for it to pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<HealthcheckConfig>,
+    /// How the image is built, defaults to `./Dockerfile` with the project as context.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildConfig>,
+    /// Limits applied to each of the app's containers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourcesConfig>,
+    /// Databases run next to the app, reachable only from its containers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<SidecarsConfig>,
+    /// Set when the app was moved out of the shared `hobby` compose project.
+    /// Its named volumes keep the names they were created with there.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legacy_volumes: bool,
+    /// Merged over the generated compose file for settings hobby does not model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compose_override: Option<ComposeOverride>,
+    /// Command run with the new image before it goes live, e.g. migrations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    /// Image versions kept on the server after a deploy, older ones are removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_images: Option<usize>,
+    /// Commands run on a schedule in the app's containers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<BTreeMap<String, JobConfig>>,
+}
+
+/// Compose project every app shared before each got its own.
+pub const LEGACY_PROJECT: &str = "hobby";
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SidecarsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postgres: Option<SidecarConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis: Option<SidecarConfig>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SidecarConfig {
+    /// Image tag, e.g. `16` for `postgres:16`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "string_or_number"
+    )]
+    pub version: Option<String>,
+}
+
+/// Accepts `version: 16` as well as `version: "16.2"`.
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value: Option<serde_yaml::Value> = Option::deserialize(deserializer)?;
+    match value {
+        None | Some(serde_yaml::Value::Null) => Ok(None),
+        Some(serde_yaml::Value::String(s)) => Ok(Some(s)),
+        Some(serde_yaml::Value::Number(n)) => Ok(Some(n.to_string())),
+        Some(_) => Err(serde::de::Error::custom(
+            "expected a version like 16 or \"16.2\"",
+        )),
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ResourcesConfig {
+    /// Memory limit in Docker's format, e.g. `512m` or `1g`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// Number of CPUs the container may use, e.g. `0.5`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// Maximum number of processes and threads in the container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct BuildConfig {
+    /// Directory sent to Docker as the build context, relative to the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Dockerfile path relative to the project, defaults to `Dockerfile` in the context.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dockerfile: Option<String>,
+    /// Stage of a multi-stage Dockerfile to build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Build arguments. An argument without a value is taken from the local
+    /// environment, so secrets never end up in hobby.yml.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, Option<String>>,
+    /// External cache sources, passed to `--cache-from`, e.g. `type=registry,ref=...`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cache_from: Vec<String>,
+    /// Cache exports, passed to `--cache-to`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cache_to: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_cache: bool,
+}
+
+impl BuildConfig {
+    pub fn context(&self) -> &str {
+        self.context.as_deref().unwrap_or(".")
+    }
+
+    pub fn dockerfile(&self) -> String {
+        match &self.dockerfile {
+            Some(dockerfile) => dockerfile.clone(),
+            None => Path::new(self.context())
+                .join("Dockerfile")
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct HealthcheckConfig {
+    /// HTTP path requested on the app's port inside the container, e.g. `/health`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Command run inside the container instead, healthy when it exits with 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Time between checks, e.g. `10s` or `1m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// Consecutive failed checks before the container counts as unhealthy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+impl HealthcheckConfig {
+    pub fn interval(&self) -> &str {
+        self.interval.as_deref().unwrap_or("10s")
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    /// Shell command Docker runs inside the container to check its health.
+    pub fn test_command(&self, port: u16) -> Result<String> {
+        match (&self.path, &self.command) {
+            (Some(path), None) => {
+                let url = format!("http://localhost:{}/{}", port, path.trim_start_matches('/'));
+                Ok(format!(
+                    "wget -q -O /dev/null {url} || curl -fsS -o /dev/null {url}",
+                    url = url
+                ))
+            }
+            (None, Some(command)) => Ok(command.clone()),
+            _ => Err(anyhow!(
+                "healthcheck needs exactly one of `path` or `command`"
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct RegistryConfig {
+    /// Registry host, optionally with a port, e.g. `registry.example.com`.
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Environment variable holding the password, defaults to `HOBBY_REGISTRY_PASSWORD`.
+    /// Without it the password saved by `hobby registry setup` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+}
+
+/// The user `hobby setup` creates and deploys as, unless `ssh.user` says otherwise.
+pub const DEFAULT_SSH_USER: &str = "hobby";
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SshConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Private key to authenticate with when the SSH agent cannot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl SshConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(22)
+    }
+
+    pub fn user(&self) -> &str {
+        self.user.as_deref().unwrap_or(DEFAULT_SSH_USER)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProcessConfig {
+    /// Overrides the image's default command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Routes this URL to the process through Caddy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Port the process listens on, defaults to the app's port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JobConfig {
+    /// Cron schedule, e.g. `0 3 * * *` or `@daily`, in the server's timezone.
+    pub schedule: String,
+    pub command: String,
+    /// Process whose container runs the job, defaults to the main one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+}
+
+impl AppConfig {
+    pub fn ssh(&self) -> SshConfig {
+        self.ssh.clone().unwrap_or_default()
+    }
+
+    /// Compose project the app runs in, derived from its name. Compose only
+    /// accepts lowercase letters, digits, dashes and underscores.
+    pub fn project_name(&self) -> String {
+        let name: String = self
+            .name
+            .to_lowercase()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        name.trim_start_matches(['-', '_']).to_string()
+    }
+
+    /// Docker's name for one of the app's named volumes.
+    pub fn volume_name(&self, volume: &str) -> String {
+        if self.legacy_volumes {
+            format!("{}_{}", LEGACY_PROJECT, volume)
+        } else {
+            format!("{}_{}", self.project_name(), volume)
+        }
+    }
+
+    pub fn build(&self) -> BuildConfig {
+        self.build.clone().unwrap_or_default()
+    }
+
+    /// Compose service name of a process. The main process is named after the
+    /// app, the others are prefixed with it so apps sharing a server never
+    /// collide.
+    pub fn service_name(&self, process: Option<&str>) -> Result<String> {
+        match process {
+            None => Ok(self.name.clone()),
+            Some(process) if process == self.name => Ok(self.name.clone()),
+            Some(process) => {
+                let known = self
+                    .processes
+                    .as_ref()
+                    .is_some_and(|p| p.contains_key(process));
+                if !known {
+                    return Err(anyhow!(
+                        "Unknown process '{}', expected one of: {}",
+                        process,
+                        self.process_names().join(", ")
+                    ));
+                }
+                Ok(format!("{}-{}", self.name, process))
+            }
+        }
+    }
+
+    /// Compose service names of all processes, the main one first.
+    pub fn service_names(&self) -> Vec<String> {
+        let mut names = vec![self.name.clone()];
+        for process in self.processes.iter().flat_map(|p| p.keys()) {
+            names.push(format!("{}-{}", self.name, process));
+        }
+        names
+    }
+
+    fn process_names(&self) -> Vec<String> {
+        let mut names = vec![self.name.clone()];
+        names.extend(self.processes.iter().flat_map(|p| p.keys().cloned()));
+        names
+    }
+}
+
+/// Parses durations in the format Docker Compose uses, e.g. `500ms`, `10s`, `1h` or `1m30s`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(anyhow!("Duration is empty, e.g. 10s"));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let unit_start = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(|| anyhow!("Duration '{}' has no unit, e.g. 10s", value))?;
+        let (number, after) = rest.split_at(unit_start);
+        let unit_end = after
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_end);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| anyhow!("Invalid duration '{}'", value))?;
+        let seconds = match unit {
+            "ns" => number / 1e9,
+            "us" | "µs" => number / 1e6,
+            "ms" => number / 1e3,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => {
+                return Err(anyhow!(
+                    "Invalid duration '{}', expected units of ns, us, ms, s, m or h, e.g. 1m30s",
+                    value
+                ))
+            }
+        };
+        total = Duration::try_from_secs_f64(seconds)
+            .ok()
+            .and_then(|duration| total.checked_add(duration))
+            .ok_or_else(|| anyhow!("Duration '{}' is too long", value))?;
+        rest = after;
+    }
+    Ok(total)
+}
+
+/// Parses memory sizes in the format Docker uses, e.g. `512m`, `1g` or `1024k`, into bytes.
+pub fn parse_memory(value: &str) -> Result<u64> {
+    let lower = value.trim().to_ascii_lowercase();
+    let number_end = lower
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(number_end);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid memory size '{}'", value))?;
+    let multiplier: u64 = match unit.trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => {
+            return Err(anyhow!(
+                "Invalid memory size '{}', expected a unit of b, k, m or g",
+                value
+            ))
+        }
+    };
+    Ok((number * multiplier as f64) as u64)
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -21,18 +413,54 @@ pub struct EnvConfig {
 }
 
 pub fn load_app_config() -> Result<AppConfig> {
-    // Implement application configuration loading logic here
+    if fs::metadata("./hobby.yml").is_err() {
+        return Err(anyhow!(
+            "hobby config is missing - run 'init' command first"
+        ));
+    }
+
     let config_data = fs::read_to_string("./hobby.yml")?;
     let config: AppConfig = serde_yaml::from_str(&config_data)?;
+    validate_name(&config.name)?;
     Ok(config)
 }
 
+/// The name ends up unquoted in remote commands and paths, including the
+/// `rm -rf` of `hobby destroy`, so only plain lowercase names are accepted.
+pub fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    let valid_rest =
+        chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !valid_start || !valid_rest {
+        return Err(anyhow!(
+            "Invalid app name '{}', it must start with a lowercase letter or digit followed by lowercase letters, digits, _ or -",
+            name
+        ));
+    }
+    Ok(())
+}
+
 pub fn save_application_config(config: &AppConfig) -> Result<()> {
     let data = serde_yaml::to_string(config)?;
     fs::write("./hobby.yml", data)?;
     Ok(())
 }
 
+/// Parses a `V<n>` version tag into its number.
+pub fn parse_version(version: &str) -> Result<i64> {
+    version
+        .trim_start_matches('V')
+        .parse::<i64>()
+        .map_err(|_| anyhow!("Invalid version '{}', expected V<number>", version))
+}
+
+pub fn next_version(version: &str) -> Result<String> {
+    Ok(format!("V{}", parse_version(version)? + 1))
+}
+
 pub fn get_config_dir() -> Result<std::path::PathBuf> {
     let config_dir = my_home()?
         .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
@@ -41,7 +469,15 @@ pub fn get_config_dir() -> Result<std::path::PathBuf> {
     Ok(config_dir)
 }
 
+/// Environment variable that can hold the age secret key instead of
+/// `~/.config/hobby/key.txt`, e.g. when running from CI.
+pub const SECRET_KEY_ENV: &str = "HOBBY_AGE_KEY";
+
 pub fn load_secret_key() -> Result<String> {
+    if let Ok(secret_key) = std::env::var(SECRET_KEY_ENV) {
+        return Ok(secret_key.trim().to_string());
+    }
+
     let config_dir = get_config_dir()?;
     let secret_key_path = config_dir.join("key.txt");
 
@@ -50,20 +486,121 @@ pub fn load_secret_key() -> Result<String> {
         .map_err(|e| anyhow::anyhow!("Failed to read secret key file: {}", e))?;
 
     // Return the secret key as a SecretString
-    Ok(secret_key)
+    Ok(secret_key.trim().to_string())
 }
 
-pub fn validate_environment() -> Result<()> {
-    // Implement environment validation logic here
-    if !fs::metadata("./Dockerfile").is_ok() {
-        return Err(anyhow!("No Dockerfile found in current directory"));
+pub fn load_public_key() -> Result<String> {
+    if let Ok(secret_key) = std::env::var(SECRET_KEY_ENV) {
+        let identity = age::x25519::Identity::from_str(secret_key.trim())
+            .map_err(|e| anyhow!("Invalid age key in {}: {}", SECRET_KEY_ENV, e))?;
+        return Ok(identity.to_public().to_string());
     }
 
-    if !fs::metadata("./hobby.yml").is_ok() {
+    let public_key_path = get_config_dir()?.join("key.pub");
+    let public_key = fs::read_to_string(&public_key_path)
+        .map_err(|e| anyhow!("Failed to read public key file: {}", e))?;
+    Ok(public_key.trim().to_string())
+}
+
+/// Generates a random hex secret for passwords hobby creates itself.
+pub fn generate_secret() -> Result<String> {
+    let mut bytes = [0u8; 24];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn validate_environment(config: &AppConfig) -> Result<()> {
+    let build = config.build();
+    if !Path::new(build.context()).is_dir() {
         return Err(anyhow!(
-            "hobby config is missing - run 'init' command first"
+            "Build context {} is not a directory",
+            build.context()
         ));
     }
 
+    for volume in config.volumes.iter().flatten() {
+        volume.validate()?;
+    }
+
+    if let Some(compose_override) = &config.compose_override {
+        compose_override.load()?;
+    }
+
+    jobs::validate(config)?;
+
+    if config.keep_images == Some(0) {
+        return Err(anyhow!("keep_images must be at least 1"));
+    }
+
+    let dockerfile = build.dockerfile();
+    if fs::metadata(&dockerfile).is_err() {
+        return Err(anyhow!("No Dockerfile found at {}", dockerfile));
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_app_names() {
+        assert!(validate_name("myapp").is_ok());
+        assert!(validate_name("my-app_2").is_ok());
+        assert!(validate_name("2fa").is_ok());
+    }
+
+    #[test]
+    fn parses_memory_sizes() {
+        assert_eq!(parse_memory("100").unwrap(), 100);
+        assert_eq!(parse_memory("100b").unwrap(), 100);
+        assert_eq!(parse_memory("1024k").unwrap(), 1024 * 1024);
+        assert_eq!(parse_memory("512m").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("512MB").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("1g").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_memory("1.5g").unwrap(), 1536 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_invalid_memory_sizes() {
+        for value in ["", "m", "512x", "1t", "lots"] {
+            assert!(parse_memory(value).is_err(), "{:?} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h2m3s").unwrap(), Duration::from_secs(3723));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for value in [
+            "",
+            "10",
+            "10x",
+            "s",
+            "1m30",
+            "ten seconds",
+            "99999999999999999999h",
+        ] {
+            assert!(parse_duration(value).is_err(), "{:?} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn rejects_names_unsafe_in_paths() {
+        for name in [
+            "", "..", "../home", "my/app", "-app", "_app", "MyApp", "my app", "app;rm",
+        ] {
+            assert!(validate_name(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+}
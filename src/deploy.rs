@@ -1,33 +1,145 @@
-use crate::commands::{connect_ssh, run_ssh_commands};
-use crate::config::{self, load_app_config, load_secret_key, validate_environment, AppConfig};
+use crate::config::{
+    self, load_app_config, validate_environment, AppConfig, HealthcheckConfig, ResourcesConfig,
+};
 use crate::docker;
 use crate::env;
-use anyhow::Result;
+use crate::health;
+use crate::jobs;
+use crate::prune;
+use crate::runner::{DryRun, Runner, SshRunner};
+use crate::sidecars;
+use crate::terminal::format_bytes;
+use anyhow::{anyhow, Result};
 use std::time::Instant;
-use tracing::info;
+use tracing::{info, warn};
 
-pub fn deploy() -> Result<()> {
-    let start = Instant::now();
+/// What is already on the server for this app.
+pub struct RemoteState {
+    pub app_dir: bool,
+    pub env_file: bool,
+    pub running: bool,
+    /// The app still runs in the compose project all apps used to share.
+    pub legacy: bool,
+}
 
-    validate_environment()?;
+/// Brings the app on the server up to date with the local project. A first
+/// launch and an update run the same pipeline, the remote state only decides
+/// which steps can be skipped, so it is safe to run repeatedly.
+pub fn up(dry_run: bool) -> Result<()> {
+    let start = Instant::now();
 
+    info!("Loading app config...");
     let mut app_config = load_app_config()?;
+    validate_environment(&app_config)?;
 
-    // TODO build docker compose file again, remove the old one and write the new one
+    let runner: Box<dyn Runner> = if dry_run {
+        Box::new(DryRun::connect(&app_config)?)
+    } else {
+        Box::new(SshRunner::connect(&app_config)?)
+    };
+    let runner = runner.as_ref();
 
-    let compose = docker::build_compose_config(&app_config)?;
-    docker::write_docker_compose_file(&compose)?;
-    docker::transfer_compose_file(&app_config)?;
+    if app_config.platform.is_none() && app_config.arch.is_none() {
+        let arch = runner.query_remote(docker::DETECT_ARCH_COMMAND)?;
+        info!("Detected server architecture: {}", arch.trim());
+        app_config.arch = Some(arch.trim().to_string());
+    }
+
+    if let Some(resources) = &app_config.resources {
+        check_resources(runner, &app_config, resources)?;
+    }
+
+    let state = inspect_remote(runner, &app_config)?;
+    if state.legacy {
+        info!(
+            "{} runs in the shared '{}' compose project, moving it to '{}'...",
+            app_config.name,
+            config::LEGACY_PROJECT,
+            app_config.project_name()
+        );
+        app_config.legacy_volumes = true;
+    }
+    let previous_version = if state.running && !state.legacy {
+        docker::running_image_version(runner, &app_config).ok()
+    } else {
+        None
+    };
+    if state.running {
+        info!("{} is running, deploying an update...", app_config.name);
+    } else {
+        info!("{} is not running, launching it...", app_config.name);
+    }
+    // hobby.yml is not committed back from CI, so the server's images are
+    // the record of which versions exist. Reusing a tag would take it away
+    // from the image rollbacks rely on.
+    let remote_versions = docker::remote_image_versions(runner, &app_config)?;
+    if state.running || !remote_versions.is_empty() {
+        let local = config::parse_version(&app_config.version)?;
+        let latest = remote_versions
+            .iter()
+            .filter_map(|v| config::parse_version(v).ok())
+            .fold(local, i64::max);
+        app_config.version = config::next_version(&format!("V{}", latest))?;
+    }
+
+    if !state.app_dir {
+        runner.run_remote(&[&format!("mkdir -p {}", app_config.name)])?;
+    }
 
-    env::encrypt_and_upload_env_file(&mut app_config)?;
+    if state.running {
+        back_up_release_files(runner, &app_config)?;
+    }
+
+    sidecars::ensure_passwords(&mut app_config, dry_run)?;
+    env::encrypt_and_upload_env_file(runner, &mut app_config, !state.env_file)?;
 
     info!("Building and transferring docker image...");
-    docker::build_and_transfer_image(&app_config)?;
+    docker::build_and_transfer_image(runner, &app_config)?;
+
+    let compose = docker::build_compose_config(&app_config)?;
+    docker::upload_compose_file(runner, &app_config, &compose)?;
 
     info!("Deploying application...");
-    deploy_application(&app_config)?;
+    if state.legacy {
+        if let Err(e) = migrate_from_legacy_project(runner, &app_config, dry_run) {
+            restore_release_files(runner, &app_config)?;
+            return Err(e);
+        }
+    } else {
+        let released =
+            start_sidecars(runner, &app_config).and_then(|_| run_release(runner, &app_config));
+        if let Err(e) = released {
+            return abort_release(runner, &app_config, state.running, e);
+        }
+        if let Err(e) = deploy_application(runner, &app_config, dry_run) {
+            return roll_back_failed_release(
+                runner,
+                &app_config,
+                state.running,
+                previous_version,
+                e,
+            );
+        }
+    }
+
+    // Only now, so a failed release never leaves its jobs scheduled against
+    // the version that keeps running.
+    jobs::install(runner, &app_config)?;
+
+    if dry_run {
+        info!("Dry run finished, nothing was executed");
+        return Ok(());
+    }
 
-    update_version_and_config(&mut app_config)?;
+    config::save_application_config(&app_config)?;
+
+    // The release is live at this point, a failed cleanup only leaves
+    // images behind for `hobby prune`.
+    if let Some(keep) = app_config.keep_images {
+        if let Err(e) = prune::remove_old_images(runner, &app_config, keep) {
+            warn!("Could not remove old images: {}", e);
+        }
+    }
 
     info!(
         "Deployment completed successfully in {:?}",
@@ -37,43 +149,441 @@ pub fn deploy() -> Result<()> {
     Ok(())
 }
 
-fn deploy_application(config: &AppConfig) -> Result<()> {
-    let session = connect_ssh("hobby", &config.server)?;
+pub fn inspect_remote(runner: &dyn Runner, config: &AppConfig) -> Result<RemoteState> {
+    let project = config.project_name();
+    let output = runner.query_remote(&format!(
+        "test -d {name} && echo app_dir; \
+         test -f {name}/encrypted.env && echo env_file; \
+         cd {name} 2>/dev/null && docker compose -p {project} ps -q {name} 2>/dev/null | grep -q . && echo running; \
+         docker compose -p {legacy} ps -q {name} 2>/dev/null | grep -q . && echo legacy; \
+         true",
+        name = config.name,
+        project = project,
+        legacy = config::LEGACY_PROJECT
+    ))?;
+
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+    let legacy = project != config::LEGACY_PROJECT && lines.contains(&"legacy");
+    Ok(RemoteState {
+        app_dir: lines.contains(&"app_dir"),
+        env_file: lines.contains(&"env_file"),
+        running: lines.contains(&"running") || legacy,
+        legacy,
+    })
+}
+
+/// Makes sure the configured limits fit the server, so a typo cannot give a
+/// container more memory or CPUs than exist.
+fn check_resources(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    resources: &ResourcesConfig,
+) -> Result<()> {
+    let output = runner.query_remote("awk '/^MemTotal:/ {print $2}' /proc/meminfo; nproc")?;
+    let mut lines = output.lines().map(str::trim);
+    let total_memory = lines
+        .next()
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| anyhow!("Could not read the server's total memory"))?;
+    let total_cpus = lines
+        .next()
+        .and_then(|n| n.parse::<u32>().ok())
+        .ok_or_else(|| anyhow!("Could not read the server's CPU count"))?;
+
+    if let Some(memory) = &resources.memory {
+        let limit = config::parse_memory(memory)?;
+        if limit > total_memory {
+            return Err(anyhow!(
+                "Memory limit {} exceeds the server's {} of memory",
+                memory,
+                format_bytes(total_memory)
+            ));
+        }
+        let containers = config.service_names().len() as u64;
+        if limit * containers > total_memory {
+            warn!(
+                "{} containers with {} each can use more than the server's {} of memory",
+                containers,
+                memory,
+                format_bytes(total_memory)
+            );
+        }
+    }
+
+    if let Some(cpus) = resources.cpus {
+        if cpus <= 0.0 || cpus > total_cpus as f64 {
+            return Err(anyhow!(
+                "CPU limit {} must be above 0 and at most the server's {} CPUs",
+                cpus,
+                total_cpus
+            ));
+        }
+    }
+
+    if resources.pids == Some(0) {
+        return Err(anyhow!("pids limit must be above 0"));
+    }
+    Ok(())
+}
+
+/// Starts the new release without downtime. Every process Caddy routes to
+/// gets a new container next to the running one, and the old containers are
+/// only stopped once all new ones are healthy. Caddy load balances across
+/// both while they overlap and retries requests to the old one while it
+/// drains. Other processes are simply recreated afterwards.
+fn deploy_application(runner: &dyn Runner, config: &AppConfig, dry_run: bool) -> Result<()> {
+    let mut replaced = Vec::new();
+    let mut started = Vec::new();
+
+    for service in routed_services(config) {
+        let old = container_ids(runner, config, &service)?;
+        if old.is_empty() {
+            continue;
+        }
 
-    let mut commands = vec![format!(
-        "cd {} && docker load -i {}-latest.tar",
-        config.name, config.name
-    )];
+        info!("Starting the new {} next to the running one...", service);
+        let result = runner
+            .run_remote(&[&docker::compose_command(
+                config,
+                &format!(
+                    "up -d --no-recreate --scale {}={} {}",
+                    service,
+                    old.len() + 1,
+                    service
+                ),
+            )?])
+            .and_then(|_| container_ids(runner, config, &service));
+        let new: Vec<String> = match result {
+            Ok(ids) => ids.into_iter().filter(|id| !old.contains(id)).collect(),
+            Err(e) => {
+                remove_containers(runner, &started)?;
+                return Err(e);
+            }
+        };
+        started.extend(new.iter().cloned());
 
-    info!("Deploying application...");
-    let deploy_command = if let Some(env_config) = &config.env {
-        if !env_config.file.is_empty() {
-            let secret_key = load_secret_key()?;
-            format!(
-                "cd {} && export SOPS_AGE_KEY={} && sops exec-env encrypted.env 'docker compose -p hobby up -d'",
-                config.name, secret_key
-            )
-        } else {
-            format!("cd {} && docker compose -p hobby up -d", config.name)
+        if !dry_run {
+            let healthcheck = if service == config.name {
+                config.healthcheck.as_ref()
+            } else {
+                None
+            };
+            if let Err(e) = wait_for_containers(runner, &service, &new, healthcheck) {
+                remove_containers(runner, &started)?;
+                return Err(e);
+            }
         }
-    } else {
-        format!("cd {} && docker compose -p hobby up -d", config.name)
-    };
+        replaced.push((service, old));
+    }
 
-    commands.push(deploy_command.clone());
-    commands.push(format!(
-        "cd {} && rm {}-latest.tar",
-        config.name, config.name
-    ));
+    for (service, old) in replaced {
+        info!("Draining the previous {}...", service);
+        runner.run_remote(&[&format!(
+            "docker stop {ids} && docker rm {ids}",
+            ids = old.join(" ")
+        )])?;
+    }
+
+    runner.run_remote(&[&docker::compose_up_command(config)?])?;
+
+    // On a first launch nothing was started ahead of time, so the health
+    // check is waited for here instead.
+    if !dry_run && started.is_empty() && config.healthcheck.is_some() {
+        let containers = container_ids(runner, config, &config.name)?;
+        wait_for_containers(
+            runner,
+            &config.name,
+            &containers,
+            config.healthcheck.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Moves an app from the shared `hobby` compose project into its own without
+/// downtime. The new containers start next to the old ones, which keep
+/// serving through Caddy until the new ones are healthy. Databases cannot
+/// share their volume with a second instance, so they are stopped and
+/// started again in the new project, which the running app sees as a short
+/// restart: the new ones join the old project's network under the same
+/// names. They are started again in the old project if the move fails.
+fn migrate_from_legacy_project(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    dry_run: bool,
+) -> Result<()> {
+    let mut legacy_services = Vec::new();
+    for service in config.service_names() {
+        let ids = project_container_ids(runner, config, config::LEGACY_PROJECT, &service)?;
+        legacy_services.extend(ids);
+    }
+    let mut legacy_sidecars = Vec::new();
+    for service in sidecars::service_names(config) {
+        let ids = project_container_ids(runner, config, config::LEGACY_PROJECT, &service)?;
+        legacy_sidecars.extend(ids);
+    }
+    let legacy_network = format!(
+        "{}_{}",
+        config::LEGACY_PROJECT,
+        sidecars::network_name(config)
+    );
+
+    let mut started = Ok(());
+    if !legacy_sidecars.is_empty() {
+        warn!(
+            "{} restart in the new project, the running app cannot reach them until they are up again",
+            sidecars::service_names(config).join(", ")
+        );
+        runner.run_remote(&[&format!("docker stop {}", legacy_sidecars.join(" "))])?;
+        started = start_sidecars(runner, config)
+            .and_then(|_| connect_sidecars(runner, config, &legacy_network));
+    }
+
+    let started = started
+        .and_then(|_| run_release(runner, config))
+        .and_then(|_| runner.run_remote(&[&docker::compose_command(config, "up -d")?]))
+        .and_then(|_| {
+            if dry_run {
+                return Ok(());
+            }
+            for service in routed_services(config) {
+                let healthcheck = if service == config.name {
+                    config.healthcheck.as_ref()
+                } else {
+                    None
+                };
+                let containers = container_ids(runner, config, &service)?;
+                wait_for_containers(runner, &service, &containers, healthcheck)?;
+            }
+            Ok(())
+        });
+
+    if let Err(e) = started {
+        info!("Moving failed, the app keeps running in the old project");
+        runner.run_remote(&[&docker::compose_command(config, "down")?])?;
+        if !legacy_sidecars.is_empty() {
+            runner.run_remote(&[&format!("docker start {}", legacy_sidecars.join(" "))])?;
+        }
+        return Err(e);
+    }
+
+    let had_sidecars = !legacy_sidecars.is_empty();
+    legacy_services.extend(legacy_sidecars);
+    info!("Removing the containers of the old project...");
+    remove_containers(runner, &legacy_services)?;
+    // The move itself succeeded, a leftover network is harmless.
+    if had_sidecars {
+        if let Err(e) = disconnect_sidecars(runner, config, &legacy_network) {
+            warn!("Could not remove the network {}: {}", legacy_network, e);
+        }
+    }
+    Ok(())
+}
+
+/// Attaches the app's sidecars to `network` under their service names, so
+/// containers on it resolve them as before.
+fn connect_sidecars(runner: &dyn Runner, config: &AppConfig, network: &str) -> Result<()> {
+    for service in sidecars::service_names(config) {
+        for id in container_ids(runner, config, &service)? {
+            runner.run_remote(&[&format!(
+                "docker network connect --alias {} {} {}",
+                service, network, id
+            )])?;
+        }
+    }
+    Ok(())
+}
+
+/// Detaches the sidecars from the old project's network and removes it.
+fn disconnect_sidecars(runner: &dyn Runner, config: &AppConfig, network: &str) -> Result<()> {
+    let mut commands = Vec::new();
+    for service in sidecars::service_names(config) {
+        for id in container_ids(runner, config, &service)? {
+            commands.push(format!("docker network disconnect {} {}", network, id));
+        }
+    }
+    commands.push(format!("docker network rm {}", network));
     let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
-    run_ssh_commands(&session, &commands)?;
+    runner.run_remote(&commands)
+}
+
+fn start_sidecars(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let sidecars = sidecars::service_names(config);
+    if sidecars.is_empty() {
+        return Ok(());
+    }
+    info!("Starting {}...", sidecars.join(", "));
+    runner.run_remote(&[&docker::compose_command(
+        config,
+        &format!("up -d --wait {}", sidecars.join(" ")),
+    )?])
+}
+
+/// Runs the release command before any container of the new version starts,
+/// so a failing migration leaves the running version untouched.
+fn run_release(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let Some(release) = &config.release else {
+        return Ok(());
+    };
+    info!("Running release command `{}`...", release);
+    runner
+        .run_remote(&[&docker::release_command(config)?])
+        .map_err(|e| anyhow!("Release command failed: {}", e))?;
+    info!("Release command finished");
     Ok(())
 }
 
-fn update_version_and_config(config: &mut AppConfig) -> Result<()> {
-    let version = config.version.trim_start_matches('V');
-    let version_int = version.parse::<i64>()?;
-    config.version = format!("V{}", version_int + 1);
-    config::save_application_config(config)?;
+/// Services Caddy routes requests to, the main process and any process with a URL.
+fn routed_services(config: &AppConfig) -> Vec<String> {
+    let mut services = vec![config.name.clone()];
+    for (process, process_config) in config.processes.iter().flatten() {
+        if process_config.url.is_some() {
+            services.push(format!("{}-{}", config.name, process));
+        }
+    }
+    services
+}
+
+fn container_ids(runner: &dyn Runner, config: &AppConfig, service: &str) -> Result<Vec<String>> {
+    project_container_ids(runner, config, &config.project_name(), service)
+}
+
+fn project_container_ids(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    project: &str,
+    service: &str,
+) -> Result<Vec<String>> {
+    let output = runner.query_remote(&format!(
+        "cd {} 2>/dev/null && docker compose -p {} ps -q {} 2>/dev/null || true",
+        config.name, project, service
+    ))?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_string())
+        .collect())
+}
+
+/// Waits for the new containers of a service, printing the logs of the
+/// first one that fails.
+fn wait_for_containers(
+    runner: &dyn Runner,
+    service: &str,
+    containers: &[String],
+    healthcheck: Option<&HealthcheckConfig>,
+) -> Result<()> {
+    if containers.is_empty() {
+        return Err(anyhow!("No new container was started for {}", service));
+    }
+    info!("Waiting for {} to become healthy...", service);
+    for container in containers {
+        if let Err(e) = health::wait_until_healthy(runner, container, healthcheck) {
+            info!("Last log lines of {}:", service);
+            println!("{}", health::recent_logs(runner, container));
+            return Err(anyhow!("{} {}", service, e));
+        }
+    }
+    info!("{} is healthy", service);
     Ok(())
 }
+
+fn remove_containers(runner: &dyn Runner, containers: &[String]) -> Result<()> {
+    if containers.is_empty() {
+        return Ok(());
+    }
+    runner.run_remote(&[&format!("docker rm -f {}", containers.join(" "))])
+}
+
+/// Puts the previous release back in place after the new one failed to go
+/// live. The old containers are started from the compose and env files they
+/// were created with, so nothing of the failed release reaches them. The
+/// failed image is removed so neither `hobby rollback` nor image retention
+/// mistake it for a working version. The next deploy gets a new version
+/// number.
+fn roll_back_failed_release(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    was_running: bool,
+    previous_version: Option<String>,
+    error: anyhow::Error,
+) -> Result<()> {
+    let failed_version = &config.version;
+    if !was_running {
+        return Err(anyhow!("{} failed to start: {}", failed_version, error));
+    }
+
+    let previous = previous_version.unwrap_or_else(|| "the previous release".to_string());
+    info!("Rolling back to {}...", previous);
+    restore_release_files(runner, config)?;
+    runner.run_remote(&[&docker::compose_up_command(config)?])?;
+    remove_failed_image(runner, config, failed_version);
+    Err(anyhow!(
+        "{} failed to start: {}. Rolled back to {}",
+        failed_version,
+        error,
+        previous
+    ))
+}
+
+/// Gives up on a release whose release command failed. No container of the
+/// new version was started, so the old ones keep running untouched once
+/// their files are back in place.
+fn abort_release(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    was_running: bool,
+    error: anyhow::Error,
+) -> Result<()> {
+    if was_running {
+        restore_release_files(runner, config)?;
+        remove_failed_image(runner, config, &config.version);
+    }
+    Err(anyhow!("{} was not deployed: {}", config.version, error))
+}
+
+/// Server files the running release was started from.
+const RELEASE_FILES: [&str; 2] = ["docker-compose.yaml", "encrypted.env"];
+
+/// Keeps a copy of the running release's files next to them before the new
+/// release replaces them.
+fn back_up_release_files(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let commands: Vec<String> = RELEASE_FILES
+        .iter()
+        .map(|file| {
+            format!(
+                "cd {name} && if [ -f {file} ]; then cp -p {file} {file}.previous; else rm -f {file}.previous; fi",
+                name = config.name,
+                file = file
+            )
+        })
+        .collect();
+    let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+    runner.run_remote(&commands)
+}
+
+/// Puts the files saved by [`back_up_release_files`] back in place, removing
+/// the ones the previous release did not have.
+fn restore_release_files(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let commands: Vec<String> = RELEASE_FILES
+        .iter()
+        .map(|file| {
+            format!(
+                "cd {name} && if [ -f {file}.previous ]; then mv -f {file}.previous {file}; else rm -f {file}; fi",
+                name = config.name,
+                file = file
+            )
+        })
+        .collect();
+    let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+    runner.run_remote(&commands)
+}
+
+fn remove_failed_image(runner: &dyn Runner, config: &AppConfig, version: &str) {
+    let image = format!("{}:{}", docker::image_repository(config), version);
+    if let Err(e) = runner.run_remote(&[&format!("docker image rm {}", image)]) {
+        warn!("Could not remove the failed image {}: {}", image, e);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::commands::{connect_app, run_ssh_commands};
+use crate::config::{load_app_config, LEGACY_PROJECT};
+use crate::docker;
+use crate::jobs;
+use crate::sidecars;
+use anyhow::Result;
+use std::io::{self, Write};
+use tracing::info;
+
+pub fn destroy(volumes: bool, yes: bool) -> Result<()> {
+    let app_config = load_app_config()?;
+
+    if !yes && !confirm(&app_config.name, &app_config.server, volumes)? {
+        info!("Aborted, nothing was removed");
+        return Ok(());
+    }
+
+    let session = connect_app(&app_config)?;
+
+    info!("Stopping and removing containers...");
+    let down_flags = if volumes { " --volumes" } else { "" };
+    // Apps deployed before each got its own compose project may still have
+    // containers in the shared one.
+    let mut services = app_config.service_names();
+    services.extend(sidecars::service_names(&app_config));
+    let down_command = format!(
+        "if [ -f {name}/docker-compose.yaml ]; then cd {name} && docker compose -p {project} down{flags} && docker compose -p {legacy} rm -sf {services}; fi",
+        name = app_config.name,
+        project = app_config.project_name(),
+        flags = down_flags,
+        legacy = LEGACY_PROJECT,
+        services = services.join(" ")
+    );
+
+    info!("Removing images and app directory...");
+    let image_command = format!(
+        "docker image ls {} -q | sort -u | xargs -r docker image rm -f",
+        docker::image_repository(&app_config)
+    );
+    let directory_command = format!("rm -rf ~/{}", app_config.name);
+    let jobs_command = format!(
+        "if [ -f {}/run-job ]; then {}; fi",
+        app_config.name,
+        jobs::remove_crontab_block_command(&app_config)
+    );
+
+    let mut commands = vec![down_command, jobs_command, image_command, directory_command];
+    if volumes {
+        // Covers volumes left behind by an earlier destroy without --volumes,
+        // when the compose file is already gone.
+        let mut volume_names: Vec<String> = app_config
+            .volumes
+            .iter()
+            .flatten()
+            .filter_map(|volume| volume.named_volume())
+            .map(|name| name.to_string())
+            .collect();
+        volume_names.extend(sidecars::volume_names(&app_config));
+        for volume in volume_names {
+            commands.push(format!(
+                "docker volume rm -f {}",
+                app_config.volume_name(&volume)
+            ));
+        }
+    }
+
+    let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+    run_ssh_commands(&session, &commands)?;
+
+    info!(
+        "{} has been removed from {}",
+        app_config.name, app_config.server
+    );
+    if !volumes {
+        info!("Named volumes were kept, run `hobby destroy --volumes` to delete them");
+    }
+    Ok(())
+}
+
+fn confirm(name: &str, server: &str, volumes: bool) -> Result<bool> {
+    print!(
+        "This will remove {} from {}{}. Continue? [y/N] ",
+        name,
+        server,
+        if volumes {
+            " including all its volumes"
+        } else {
+            ""
+        }
+    );
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -1,29 +1,59 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, process::Command};
-use tracing::info;
+use std::{collections::HashMap, fs};
+use tracing::{info, warn};
 
-use crate::config::AppConfig;
+use crate::commands::shell_quote;
+use crate::config::{load_secret_key, parse_duration, parse_version, AppConfig};
+use crate::overrides;
+use crate::registry;
+use crate::runner::Runner;
+use crate::sidecars;
+use crate::transfer;
+use crate::volumes::VolumeConfig;
 
 #[derive(Serialize, Deserialize)]
 pub struct DockerService {
     pub image: String,
     pub restart: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
     pub labels: HashMap<String, String>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub environment: Option<HashMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub volumes: Option<Vec<String>>,
+    pub volumes: Option<Vec<VolumeConfig>>,
     pub networks: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<DockerHealthcheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<u32>,
+    /// Services with a profile are left out of `up` and only started on demand.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DockerHealthcheck {
+    pub test: Vec<String>,
+    pub interval: String,
+    pub retries: u32,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DockerNetwork {
     pub external: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct DockerVolume {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub external: Option<bool>,
@@ -36,40 +66,115 @@ pub struct DockerComposeFile {
     pub services: HashMap<String, DockerService>,
     pub networks: HashMap<String, DockerNetwork>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub volumes: Option<HashMap<String, ()>>,
-}
-
-pub fn build_and_transfer_image(config: &AppConfig) -> Result<()> {
-    let commands = vec![
-        format!(
-            "docker build --tag {} --platform=linux/arm64 .",
-            config.name
-        ),
-        format!("docker save -o {}-latest.tar {}", config.name, config.name),
-        format!(
-            "scp -C {}-latest.tar hobby@{}:./{}/",
-            config.name, config.server, config.name
-        ),
-    ];
+    pub volumes: Option<HashMap<String, DockerVolume>>,
+}
+
+/// The image name without a tag, prefixed with the registry when one is configured.
+pub fn image_repository(config: &AppConfig) -> String {
+    match &config.registry {
+        Some(registry) => format!("{}/{}", registry.url, config.name),
+        None => config.name.clone(),
+    }
+}
+
+/// The image reference for the version currently set in the config, e.g. `myapp:V3`.
+pub fn image_tag(config: &AppConfig) -> String {
+    format!("{}:{}", image_repository(config), config.version)
+}
+
+/// Remote command printing the server's architecture in Debian naming (amd64, arm64, ...).
+pub const DETECT_ARCH_COMMAND: &str = "dpkg --print-architecture";
 
-    for cmd_str in commands {
-        let output = Command::new("sh").arg("-c").arg(&cmd_str).output()?;
+/// The platform to build the image for, the explicit override or the server's architecture.
+pub fn platform(config: &AppConfig) -> Result<String> {
+    if let Some(platform) = &config.platform {
+        return Ok(platform.clone());
+    }
+    let arch = config
+        .arch
+        .as_ref()
+        .ok_or_else(|| anyhow!("Server architecture is unknown, set `platform:` in hobby.yml"))?;
+    docker_platform(arch)
+}
 
-        if !output.status.success() {
+/// Translates Debian's architecture names into Docker platforms.
+fn docker_platform(arch: &str) -> Result<String> {
+    let platform = match arch {
+        "amd64" => "linux/amd64",
+        "arm64" => "linux/arm64",
+        "armhf" => "linux/arm/v7",
+        "armel" => "linux/arm/v6",
+        "i386" => "linux/386",
+        "ppc64el" => "linux/ppc64le",
+        "s390x" => "linux/s390x",
+        "riscv64" => "linux/riscv64",
+        _ => {
             return Err(anyhow!(
-                "Command failed: {}\nOutput: {}\nError: {}",
-                cmd_str,
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            ));
+                "Unsupported server architecture '{}', set `platform:` in hobby.yml",
+                arch
+            ))
         }
+    };
+    Ok(platform.to_string())
+}
+
+/// Builds the image and makes it available to Docker on the server, either
+/// through the configured registry or by streaming it over SSH.
+pub fn build_and_transfer_image(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    runner.run_local(&build_command(config)?)?;
+
+    match &config.registry {
+        Some(registry) => registry::push_and_pull_image(runner, config, registry)?,
+        None => transfer::transfer_image(runner, config)?,
     }
 
     info!("Image built and transferred successfully");
-    fs::remove_file(format!("{}-latest.tar", config.name))?;
     Ok(())
 }
 
+fn build_command(config: &AppConfig) -> Result<String> {
+    let build = config.build();
+    let mut args = vec![
+        "docker build".to_string(),
+        format!("--tag {}", image_tag(config)),
+        format!("--platform={}", platform(config)?),
+        format!("--file {}", shell_quote(&build.dockerfile())),
+    ];
+    if let Some(target) = &build.target {
+        args.push(format!("--target {}", shell_quote(target)));
+    }
+    for (name, value) in &build.args {
+        match value {
+            Some(value) => args.push(format!(
+                "--build-arg {}",
+                shell_quote(&format!("{}={}", name, value))
+            )),
+            // Docker reads the value from its own environment, which keeps
+            // it out of the command line and the dry run output.
+            None => {
+                if std::env::var_os(name).is_none() {
+                    return Err(anyhow!(
+                        "Build argument {} has no value and is not set in the environment",
+                        name
+                    ));
+                }
+                args.push(format!("--build-arg {}", shell_quote(name)));
+            }
+        }
+    }
+    for cache in &build.cache_from {
+        args.push(format!("--cache-from {}", shell_quote(cache)));
+    }
+    for cache in &build.cache_to {
+        args.push(format!("--cache-to {}", shell_quote(cache)));
+    }
+    if build.no_cache {
+        args.push("--no-cache".to_string());
+    }
+    args.push(shell_quote(build.context()));
+    Ok(args.join(" "))
+}
+
 pub fn create_docker_env(file: &str) -> Result<HashMap<String, String>> {
     // Implement environment variable creation logic here
     let env_data = fs::read_to_string(file)?;
@@ -95,86 +200,266 @@ pub fn create_docker_env(file: &str) -> Result<HashMap<String, String>> {
     Ok(env_map)
 }
 
-pub fn build_compose_config(config: &AppConfig) -> Result<DockerComposeFile> {
-    info!("Building docker-compose file...");
-    let mut service = DockerService {
-        image: config.name.clone(),
+fn build_service(
+    config: &AppConfig,
+    command: Option<String>,
+    url: Option<&str>,
+    port: u16,
+    environment: &Option<HashMap<String, String>>,
+) -> DockerService {
+    let mut labels = HashMap::new();
+    if let Some(url) = url {
+        labels.insert("caddy".to_string(), url.to_string());
+        labels.insert(
+            "caddy.reverse_proxy".to_string(),
+            format!("{{{{upstreams {}}}}}", port),
+        );
+        // While deploys swap containers, requests to one that is going away
+        // are retried on the other instead of failing.
+        labels.insert(
+            "caddy.reverse_proxy.lb_try_duration".to_string(),
+            "10s".to_string(),
+        );
+    }
+
+    let resources = config.resources.clone().unwrap_or_default();
+    DockerService {
+        image: image_tag(config),
         restart: "unless-stopped".to_string(),
-        labels: {
-            let mut labels = HashMap::new();
-            labels.insert("caddy".to_string(), config.url.clone());
-            labels.insert(
-                "caddy.reverse_proxy".to_string(),
-                format!("{{{{upstreams {}}}}}", config.port),
-            );
-            labels
-        },
+        command,
+        labels,
         networks: vec!["caddy".to_string()],
-        environment: None,
+        environment: environment.clone(),
         volumes: config.volumes.clone(),
-    };
+        healthcheck: None,
+        mem_limit: resources.memory,
+        cpus: resources.cpus,
+        pids_limit: resources.pids,
+        profiles: None,
+    }
+}
+
+pub fn build_compose_config(config: &AppConfig) -> Result<DockerComposeFile> {
+    info!("Building docker-compose file...");
 
+    let mut environment = None;
     if let Some(env_config) = &config.env {
         if !env_config.file.is_empty() {
-            let env_map = create_docker_env(&env_config.file)?;
-            service.environment = Some(env_map);
+            environment = Some(create_docker_env(&env_config.file)?);
         }
     }
 
-    let compose = DockerComposeFile {
-        services: {
-            let mut services = HashMap::new();
-            services.insert(config.name.clone(), service);
-            services
-        },
-        networks: {
-            let mut networks = HashMap::new();
-            networks.insert("caddy".to_string(), DockerNetwork { external: true });
-            networks
+    let mut networks = HashMap::new();
+    networks.insert(
+        "caddy".to_string(),
+        DockerNetwork {
+            external: true,
+            internal: None,
         },
-        volumes: {
-            let mut volumes: HashMap<String, ()> = HashMap::new();
-            if let Some(vols) = &config.volumes {
-                for v in vols {
-                    let parts: Vec<&str> = v.split(":").collect();
-                    if parts.len() == 2 {
-                        volumes.insert(parts[0].to_string(), ());
-                    }
-                }
+    );
+
+    let mut volumes: HashMap<String, DockerVolume> = HashMap::new();
+    for volume in config.volumes.iter().flatten() {
+        volume.validate()?;
+        if let Some(name) = volume.named_volume() {
+            volumes.insert(name.to_string(), DockerVolume::default());
+        }
+    }
+
+    let mut services = HashMap::new();
+    if config.services.is_some() {
+        let mut app_environment = environment.take().unwrap_or_default();
+        sidecars::add_sidecars(
+            config,
+            &mut services,
+            &mut networks,
+            &mut volumes,
+            &mut app_environment,
+        )?;
+        environment = Some(app_environment);
+    }
+
+    let mut main = build_service(config, None, Some(&config.url), config.port, &environment);
+    if let Some(healthcheck) = &config.healthcheck {
+        parse_duration(healthcheck.interval())?;
+        main.healthcheck = Some(DockerHealthcheck {
+            test: vec![
+                "CMD-SHELL".to_string(),
+                healthcheck.test_command(config.port)?,
+            ],
+            interval: healthcheck.interval().to_string(),
+            retries: healthcheck.retries(),
+        });
+    }
+
+    services.insert(config.name.clone(), main);
+    for (process, process_config) in config.processes.iter().flatten() {
+        services.insert(
+            config.service_name(Some(process))?,
+            build_service(
+                config,
+                process_config.command.clone(),
+                process_config.url.as_deref(),
+                process_config.port.unwrap_or(config.port),
+                &environment,
+            ),
+        );
+    }
+
+    if let Some(release) = &config.release {
+        let name = release_service_name(config);
+        if services.contains_key(&name) {
+            return Err(anyhow!(
+                "Process 'release' collides with the release command, rename it"
+            ));
+        }
+        // Not routed, so Caddy never sends requests to it while it runs.
+        let mut service = build_service(
+            config,
+            Some(format!("sh -c {}", shell_quote(release))),
+            None,
+            config.port,
+            &environment,
+        );
+        service.restart = "no".to_string();
+        service.profiles = Some(vec!["release".to_string()]);
+        services.insert(name, service);
+    }
+
+    if config.services.is_some() {
+        let mut app_services = config.service_names();
+        if config.release.is_some() {
+            app_services.push(release_service_name(config));
+        }
+        for name in app_services {
+            if let Some(service) = services.get_mut(&name) {
+                service.networks.push(sidecars::network_name(config));
             }
-            Some(volumes)
-        },
-    };
+        }
+    }
 
-    Ok(compose)
-}
+    if config.legacy_volumes {
+        for (key, volume) in volumes.iter_mut() {
+            volume.name = Some(config.volume_name(key));
+        }
+    }
 
-pub fn write_docker_compose_file(config: &DockerComposeFile) -> Result<()> {
-    let data = serde_yaml::to_string(config)?;
-    fs::write("docker-compose.yaml", data)?;
-    Ok(())
+    Ok(DockerComposeFile {
+        services,
+        networks,
+        volumes: Some(volumes),
+    })
 }
 
-pub fn transfer_compose_file(config: &AppConfig) -> Result<()> {
-    info!("Transferring docker-compose file...");
+/// Compose service the release command runs in, from the new image with the
+/// app's environment and volumes.
+fn release_service_name(config: &AppConfig) -> String {
+    format!("{}-release", config.name)
+}
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(format!(
-            "scp -C docker-compose.yaml hobby@{}:./{}/",
-            config.server,
-            config.name // Replace with actual server address
-        ))
-        .output()?;
+/// Command running the release command to completion in a one-off container.
+pub fn release_command(config: &AppConfig) -> Result<String> {
+    compose_command(
+        config,
+        &format!("run --rm -T {}", release_service_name(config)),
+    )
+}
 
-    if !output.status.success() {
-        return Err(anyhow!(
-            "Failed to transfer docker-compose file:\nOutput: {}\nError: {}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        ));
+/// Command running `docker compose <args>` in the app's directory, with the
+/// env file decrypted into its environment when one is configured.
+pub fn compose_command(config: &AppConfig, args: &str) -> Result<String> {
+    if let Some(env_config) = &config.env {
+        if !env_config.file.is_empty() {
+            let secret_key = load_secret_key()?;
+            return Ok(format!(
+                "cd {} && export SOPS_AGE_KEY={} && sops exec-env encrypted.env 'docker compose -p {} {}'",
+                config.name,
+                secret_key,
+                config.project_name(),
+                args
+            ));
+        }
     }
+    Ok(format!(
+        "cd {} && docker compose -p {} {}",
+        config.name,
+        config.project_name(),
+        args
+    ))
+}
+
+/// Command that (re)starts the compose project.
+pub fn compose_up_command(config: &AppConfig) -> Result<String> {
+    compose_command(config, "up -d")
+}
 
-    info!("docker-compose file transferred successfully");
+/// Lists the versions of the app image that are present on the server, oldest first.
+pub fn remote_image_versions(runner: &dyn Runner, config: &AppConfig) -> Result<Vec<String>> {
+    let output = runner.query_remote(&format!(
+        "docker image ls {} --format '{{{{.Tag}}}}'",
+        image_repository(config)
+    ))?;
+
+    let mut versions: Vec<(i64, String)> = output
+        .lines()
+        .map(str::trim)
+        .filter_map(|tag| parse_version(tag).ok().map(|n| (n, tag.to_string())))
+        .collect();
+    versions.sort();
+    Ok(versions.into_iter().map(|(_, tag)| tag).collect())
+}
+
+/// Returns the version tag of the image the app container is currently running.
+pub fn running_image_version(runner: &dyn Runner, config: &AppConfig) -> Result<String> {
+    let image = runner.query_remote(&format!(
+        "cd {} && docker inspect --format '{{{{.Config.Image}}}}' $(docker compose -p {} ps -q {})",
+        config.name,
+        config.project_name(),
+        config.name
+    ))?;
+
+    let image = image.trim();
+    image
+        .rsplit_once(':')
+        .map(|(_, tag)| tag.to_string())
+        .ok_or_else(|| anyhow!("Running image '{}' has no version tag", image))
+}
+
+pub fn upload_compose_file(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    compose: &DockerComposeFile,
+) -> Result<()> {
+    info!("Uploading docker-compose file...");
+    let mut value = serde_yaml::to_value(compose)?;
+    let conflicts = overrides::apply(config, &mut value)?;
+    if !conflicts.is_empty() {
+        warn!("compose_override changed generated settings:");
+        for conflict in &conflicts {
+            warn!("  {}", conflict);
+        }
+    }
+    let data = serde_yaml::to_string(&value)?;
+    runner.upload_bytes(
+        data.as_bytes(),
+        &format!("{}/docker-compose.yaml", config.name),
+        0o644,
+    )?;
+    info!("docker-compose file uploaded successfully");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_debian_architectures_to_platforms() {
+        assert_eq!(docker_platform("amd64").unwrap(), "linux/amd64");
+        assert_eq!(docker_platform("arm64").unwrap(), "linux/arm64");
+        assert_eq!(docker_platform("armhf").unwrap(), "linux/arm/v7");
+        assert_eq!(docker_platform("i386").unwrap(), "linux/386");
+        assert_eq!(docker_platform("ppc64el").unwrap(), "linux/ppc64le");
+        assert!(docker_platform("mips64el").is_err());
+    }
+}
@@ -1,7 +1,7 @@
-use crate::config::AppConfig;
+use crate::config::{load_public_key, AppConfig};
+use crate::runner::Runner;
 use anyhow::{anyhow, Result};
-use homedir::my_home;
-use std::{fs, process::Command};
+use std::fs;
 use tracing::info;
 
 pub fn get_env_file_hash(config: &AppConfig) -> Result<String> {
@@ -18,7 +18,13 @@ pub fn get_env_file_hash(config: &AppConfig) -> Result<String> {
     }
 }
 
-pub fn encrypt_and_upload_env_file(config: &mut AppConfig) -> Result<()> {
+/// Encrypts the env file and uploads it when it changed since the last upload,
+/// or always when `force` is set.
+pub fn encrypt_and_upload_env_file(
+    runner: &dyn Runner,
+    config: &mut AppConfig,
+    force: bool,
+) -> Result<()> {
     if let Some(env) = &config.env {
         if env.file.is_empty() {
             return Ok(());
@@ -26,55 +32,24 @@ pub fn encrypt_and_upload_env_file(config: &mut AppConfig) -> Result<()> {
 
         let hash = get_env_file_hash(config)?;
 
-        if hash == env.hash {
+        if hash == env.hash && !force {
             info!("Environment file has not changed, skipping encryption and upload");
             return Ok(());
         }
 
         info!("Encrypting environment file...");
-        let config_dir = my_home()?
-            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
-            .join(".config/hobby");
-        let public_key_path = config_dir.join("key.pub");
-        let public_key = fs::read_to_string(public_key_path)?.trim().to_string();
+        let public_key = load_public_key()?;
 
         let env_path = format!("./{}", env.file);
 
-        let encrypt_command = format!(
-            "sops encrypt --age {} {} > encrypted.env",
-            public_key, env_path
-        );
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&encrypt_command)
-            .output()?;
+        let encrypt_command = format!("sops encrypt --age {} {}", public_key, env_path);
+        let encrypted = runner
+            .run_local_output(&encrypt_command)
+            .map_err(|e| anyhow!("Failed to encrypt env file: {}", e))?;
 
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to encrypt env file: {}\nOutput: {}\nError: {}",
-                encrypt_command,
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
-
-        info!("Syncing encrypted environment file to server...");
-        let sync_command = format!(
-            "rsync -v encrypted.env hobby@{}:./{}",
-            config.server, config.name
-        );
-        let output = Command::new("sh").arg("-c").arg(&sync_command).output()?;
-
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to sync env file: {}\nOutput: {}\nError: {}",
-                sync_command,
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+        info!("Uploading encrypted environment file to server...");
+        runner.upload_bytes(&encrypted, &format!("{}/encrypted.env", config.name), 0o600)?;
 
-        fs::remove_file("encrypted.env")?;
         if let Some(env) = &mut config.env {
             env.hash = hash;
         }
@@ -0,0 +1,45 @@
+use crate::commands::{connect_app, run_interactive_ssh_command, shell_quote};
+use crate::config::load_app_config;
+use crate::terminal;
+use anyhow::{anyhow, Result};
+
+const SHELL_COMMAND: &str = "if command -v bash >/dev/null; then exec bash; else exec sh; fi";
+
+/// Runs a command inside the app container and returns its exit code.
+pub fn exec(process: Option<String>, command: Vec<String>) -> Result<i32> {
+    if command.is_empty() {
+        return Err(anyhow!("No command given, usage: hobby exec -- <cmd>"));
+    }
+    attach(process, &command)
+}
+
+/// Opens an interactive shell inside the app container.
+pub fn shell(process: Option<String>) -> Result<i32> {
+    attach(
+        process,
+        &[
+            "sh".to_string(),
+            "-c".to_string(),
+            SHELL_COMMAND.to_string(),
+        ],
+    )
+}
+
+fn attach(process: Option<String>, command: &[String]) -> Result<i32> {
+    let app_config = load_app_config()?;
+    let service = app_config.service_name(process.as_deref())?;
+    let session = connect_app(&app_config)?;
+
+    let tty = terminal::stdin_is_tty();
+    let args: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+    let remote_command = format!(
+        "cd {} && docker compose -p {} exec{} {} {}",
+        app_config.name,
+        app_config.project_name(),
+        if tty { "" } else { " -T" },
+        service,
+        args.join(" ")
+    );
+
+    run_interactive_ssh_command(&session, &remote_command, tty)
+}
@@ -0,0 +1,68 @@
+use crate::config::{parse_duration, HealthcheckConfig};
+use crate::runner::Runner;
+use anyhow::{anyhow, Result};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the container's health status is polled while waiting.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Extra time on top of the checks themselves, Docker's default check timeout.
+/// Also bounds how long a container may take to start at all.
+const GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How long a container without a health check has to keep running before
+/// it counts as started.
+const STARTUP_PERIOD: Duration = Duration::from_secs(5);
+
+/// Waits until Docker reports `container` as healthy, or, without a health
+/// check, until it has kept running for a few seconds. Fails as soon as it is
+/// reported unhealthy or stops running.
+pub fn wait_until_healthy(
+    runner: &dyn Runner,
+    container: &str,
+    healthcheck: Option<&HealthcheckConfig>,
+) -> Result<()> {
+    let start = Instant::now();
+    let timeout = match healthcheck {
+        Some(healthcheck) => {
+            parse_duration(healthcheck.interval())? * (healthcheck.retries() + 1) + GRACE_PERIOD
+        }
+        None => STARTUP_PERIOD + GRACE_PERIOD,
+    };
+
+    loop {
+        let output = runner.query_remote(&format!(
+            "docker inspect --format '{{{{.State.Status}}}} {{{{if .State.Health}}}}{{{{.State.Health.Status}}}}{{{{end}}}}' {}",
+            container
+        ))?;
+        let mut parts = output.split_whitespace();
+        let state = parts.next().unwrap_or("missing");
+        let health = parts.next().unwrap_or("");
+
+        match (state, health) {
+            ("running", "healthy") => return Ok(()),
+            ("running", "unhealthy") => return Err(anyhow!("container is unhealthy")),
+            ("running", _) if healthcheck.is_none() && start.elapsed() >= STARTUP_PERIOD => {
+                return Ok(())
+            }
+            ("running", _) | ("created", _) | ("restarting", _) => {}
+            (state, _) => return Err(anyhow!("container is {}", state)),
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(anyhow!(
+                "container did not become healthy within {}s",
+                timeout.as_secs()
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The last lines a container logged, for failure reports.
+pub fn recent_logs(runner: &dyn Runner, container: &str) -> String {
+    runner
+        .query_remote(&format!("docker logs --tail 50 {} 2>&1", container))
+        .unwrap_or_else(|e| format!("Could not fetch logs: {}", e))
+}
@@ -0,0 +1,233 @@
+use crate::commands::{connect_app, run_ssh_command_output, shell_quote, stream_ssh_command};
+use crate::config::{load_app_config, AppConfig};
+use crate::runner::Runner;
+use crate::terminal;
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+const CRON_KEYWORDS: [&str; 8] = [
+    "@yearly",
+    "@annually",
+    "@monthly",
+    "@weekly",
+    "@daily",
+    "@midnight",
+    "@hourly",
+    "@reboot",
+];
+
+/// Script in the app directory that runs a job by name, used by cron and
+/// `hobby jobs run` alike.
+fn script_path(config: &AppConfig) -> String {
+    format!("{}/run-job", config.name)
+}
+
+fn log_dir(config: &AppConfig) -> String {
+    format!("{}/logs", config.name)
+}
+
+/// Lines around the app's entries in the hobby user's crontab, so they can
+/// be replaced without touching anything else in it.
+fn crontab_markers(config: &AppConfig) -> (String, String) {
+    (
+        format!("# BEGIN hobby {}", config.name),
+        format!("# END hobby {}", config.name),
+    )
+}
+
+pub fn validate(config: &AppConfig) -> Result<()> {
+    for (name, job) in config.jobs.iter().flatten() {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(anyhow!(
+                "Job name '{}' may only contain letters, digits, - and _",
+                name
+            ));
+        }
+        if !valid_schedule(&job.schedule) {
+            return Err(anyhow!(
+                "Job '{}' has an invalid schedule '{}', expected five cron fields or one of {}",
+                name,
+                job.schedule,
+                CRON_KEYWORDS.join(", ")
+            ));
+        }
+        if job.command.trim().is_empty() {
+            return Err(anyhow!("Job '{}' has no command", name));
+        }
+        config.service_name(job.process.as_deref())?;
+    }
+    Ok(())
+}
+
+fn valid_schedule(schedule: &str) -> bool {
+    if schedule.starts_with('@') {
+        return CRON_KEYWORDS.contains(&schedule);
+    }
+    let fields: Vec<&str> = schedule.split_whitespace().collect();
+    fields.len() == 5
+        && fields.iter().all(|field| {
+            field
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '*' | ',' | '-' | '/'))
+        })
+}
+
+/// Installs the app's jobs on the server: the script that runs them and a
+/// block in the crontab that schedules them. Jobs removed from hobby.yml are
+/// removed from the server as well.
+pub fn install(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let jobs = config.jobs.clone().unwrap_or_default();
+    let script = script_path(config);
+    if jobs.is_empty() {
+        runner.run_remote(&[&format!(
+            "if [ -f {script} ]; then {remove} && rm -f {script}; fi",
+            script = script,
+            remove = remove_crontab_block_command(config)
+        )])?;
+        return Ok(());
+    }
+
+    info!("Installing {} scheduled job(s)...", jobs.len());
+    runner.upload_bytes(run_script(config)?.as_bytes(), &script, 0o755)?;
+    runner.run_remote(&[&format!("mkdir -p {}", log_dir(config))])?;
+
+    let (begin, end) = crontab_markers(config);
+    let mut block = format!("{}\n", begin);
+    for (name, job) in &jobs {
+        // Cron starts in the home directory, each run replaces the output
+        // of the previous one.
+        block.push_str(&format!(
+            "{} {} {} > {}/{}.log 2>&1\n",
+            job.schedule,
+            script,
+            name,
+            log_dir(config),
+            name
+        ));
+    }
+    block.push_str(&format!("{}\n", end));
+
+    runner.run_remote_with_input(
+        &format!(
+            "{{ crontab -l 2>/dev/null | {}; cat; }} | crontab -",
+            strip_block_filter(config)
+        ),
+        block.as_bytes(),
+    )
+}
+
+/// Command removing the app's block from the crontab.
+pub fn remove_crontab_block_command(config: &AppConfig) -> String {
+    format!(
+        "crontab -l 2>/dev/null | {} | crontab -",
+        strip_block_filter(config)
+    )
+}
+
+fn strip_block_filter(config: &AppConfig) -> String {
+    let (begin, end) = crontab_markers(config);
+    format!(
+        "awk -v b={} -v e={} '$0 == b {{ skip = 1 }} !skip {{ print }} $0 == e {{ skip = 0 }}'",
+        shell_quote(&begin),
+        shell_quote(&end)
+    )
+}
+
+/// Jobs run in the app's running container, which already has the
+/// decrypted environment, so the server never needs the secret key.
+fn run_script(config: &AppConfig) -> Result<String> {
+    let mut cases = String::new();
+    for (name, job) in config.jobs.iter().flatten() {
+        cases.push_str(&format!(
+            "  {}) service={}; command={} ;;\n",
+            name,
+            config.service_name(job.process.as_deref())?,
+            shell_quote(&job.command)
+        ));
+    }
+
+    Ok(format!(
+        r#"#!/bin/sh
+# Runs one of {name}'s scheduled jobs. Written by hobby on every deploy.
+cd "$(dirname "$0")" || exit 1
+case "$1" in
+{cases}  *) echo "Unknown job: $1, deploy with hobby up after adding it" >&2; exit 1 ;;
+esac
+container=$(docker compose -p {project} ps -q "$service")
+if [ -z "$container" ]; then
+  echo "$service is not running" >&2
+  exit 1
+fi
+echo "$(date -u +%Y-%m-%dT%H:%M:%SZ) Running $1"
+exec docker exec "$container" sh -c "$command"
+"#,
+        name = config.name,
+        cases = cases,
+        project = config.project_name()
+    ))
+}
+
+/// Prints the configured jobs and when each last ran on the server.
+pub fn list() -> Result<()> {
+    let app_config = load_app_config()?;
+    let jobs = app_config.jobs.clone().unwrap_or_default();
+    if jobs.is_empty() {
+        info!("No jobs are configured in hobby.yml");
+        return Ok(());
+    }
+
+    let session = connect_app(&app_config)?;
+    let output = run_ssh_command_output(
+        &session,
+        &format!(
+            "cd {} 2>/dev/null && for f in *.log; do [ -f \"$f\" ] && echo \"${{f%.log}} $(date -u -r \"$f\" +%Y-%m-%dT%H:%M:%SZ)\"; done; true",
+            log_dir(&app_config)
+        ),
+    )?;
+    let last_runs: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .collect();
+
+    let header = ["JOB", "SCHEDULE", "PROCESS", "LAST RUN", "COMMAND"];
+    let mut rows = Vec::new();
+    for (name, job) in &jobs {
+        let last_run = last_runs
+            .iter()
+            .find(|(job_name, _)| job_name == name)
+            .map(|(_, time)| time.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        rows.push([
+            name.clone(),
+            job.schedule.clone(),
+            app_config.service_name(job.process.as_deref())?,
+            last_run,
+            job.command.clone(),
+        ]);
+    }
+
+    terminal::print_table(header, &rows);
+    Ok(())
+}
+
+/// Runs a job right away with its output streamed to the terminal and
+/// returns its exit code.
+pub fn run(name: String) -> Result<i32> {
+    let app_config = load_app_config()?;
+    let jobs = app_config.jobs.clone().unwrap_or_default();
+    if !jobs.contains_key(&name) {
+        let names: Vec<&str> = jobs.keys().map(|k| k.as_str()).collect();
+        return Err(anyhow!(
+            "Unknown job '{}', expected one of: {}",
+            name,
+            names.join(", ")
+        ));
+    }
+
+    let session = connect_app(&app_config)?;
+    stream_ssh_command(&session, &format!("{} {}", script_path(&app_config), name))
+}
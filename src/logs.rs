@@ -0,0 +1,42 @@
+use crate::commands::{connect_app, shell_quote, stream_ssh_command};
+use crate::config::load_app_config;
+use anyhow::{anyhow, Result};
+
+pub fn logs(
+    process: Option<String>,
+    follow: bool,
+    since: Option<String>,
+    tail: Option<String>,
+) -> Result<()> {
+    let app_config = load_app_config()?;
+    let session = connect_app(&app_config)?;
+
+    let mut command = format!(
+        "cd {} && docker compose -p {} logs",
+        app_config.name,
+        app_config.project_name()
+    );
+    if follow {
+        command.push_str(" --follow");
+    }
+    if let Some(since) = since {
+        command.push_str(&format!(" --since {}", shell_quote(&since)));
+    }
+    if let Some(tail) = tail {
+        command.push_str(&format!(" --tail {}", shell_quote(&tail)));
+    }
+    let services = match process {
+        Some(process) => vec![app_config.service_name(Some(&process))?],
+        None => app_config.service_names(),
+    };
+    command.push_str(&format!(" {}", services.join(" ")));
+
+    let exit_status = stream_ssh_command(&session, &command)?;
+    if exit_status != 0 {
+        return Err(anyhow!(
+            "docker compose logs exited with status {}",
+            exit_status
+        ));
+    }
+    Ok(())
+}
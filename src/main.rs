@@ -1,14 +1,28 @@
 mod commands;
 mod config;
 mod deploy;
+mod destroy;
 mod docker;
 mod env;
-mod launch;
+mod exec;
+mod health;
+mod jobs;
+mod logs;
+mod overrides;
+mod prune;
+mod registry;
+mod rollback;
+mod runner;
 mod setup;
+mod sidecars;
+mod status;
+mod terminal;
+mod transfer;
+mod volumes;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use tracing_subscriber;
+use config::SshConfig;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -20,9 +34,110 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    Setup { server_addr: String },
-    Launch,
-    Deploy,
+    /// Prepare a fresh server and write a default hobby.yml
+    Setup {
+        server_addr: String,
+        /// SSH port of the server
+        #[arg(long)]
+        port: Option<u16>,
+        /// Private key to use when the SSH agent cannot authenticate
+        #[arg(long)]
+        identity_file: Option<String>,
+        /// User to create on the server and deploy as, defaults to hobby
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Launch the app or deploy an update, depending on what is on the server
+    #[command(visible_aliases = ["launch", "deploy"])]
+    Up {
+        /// Print the compose file and every command instead of running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Manage the registry images are distributed through
+    Registry {
+        #[command(subcommand)]
+        command: RegistryCommands,
+    },
+    /// Switch the app back to an image that is already on the server
+    Rollback {
+        /// Version to roll back to, e.g. V3. Defaults to the one before the running version
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Stream the app's container logs
+    Logs {
+        /// Only show logs of this process, defaults to all of them
+        #[arg(short, long)]
+        process: Option<String>,
+        /// Keep streaming new log lines as they are written
+        #[arg(short, long)]
+        follow: bool,
+        /// Only show logs since a timestamp (e.g. 2024-01-02T13:23:37Z) or relative duration (e.g. 42m)
+        #[arg(long)]
+        since: Option<String>,
+        /// Number of lines to show from the end of the logs, or "all"
+        #[arg(short = 'n', long)]
+        tail: Option<String>,
+    },
+    /// Show whether the app is up and which version it is running
+    Status {
+        /// Print the status as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove the app's containers, images and directory from the server
+    Destroy {
+        /// Also delete the app's named volumes
+        #[arg(long)]
+        volumes: bool,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Remove old versions of the app's image from the server
+    Prune {
+        /// Number of versions to keep, defaults to keep_images in hobby.yml or 5
+        #[arg(long)]
+        keep: Option<usize>,
+    },
+    /// Run a command inside the running app container
+    Exec {
+        /// Process to run the command in, defaults to the main one
+        #[arg(short, long)]
+        process: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Inspect and run the app's scheduled jobs
+    Jobs {
+        #[command(subcommand)]
+        command: JobsCommands,
+    },
+    /// Open an interactive shell inside the running app container
+    Shell {
+        /// Process to open the shell in, defaults to the main one
+        #[arg(short, long)]
+        process: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RegistryCommands {
+    /// Run a registry on the app's server and deploy through it
+    Setup {
+        /// Host the registry is served on, defaults to registry.<server>.sslip.io
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum JobsCommands {
+    /// Show the configured jobs and when they last ran
+    List,
+    /// Run a job now and stream its output
+    Run { name: String },
 }
 
 fn main() -> Result<()> {
@@ -30,15 +145,67 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Setup { server_addr } => {
-            setup::setup(server_addr)?;
+        Commands::Setup {
+            server_addr,
+            port,
+            identity_file,
+            user,
+        } => {
+            let ssh = SshConfig {
+                port,
+                identity_file,
+                user,
+            };
+            setup::setup(server_addr, ssh)?;
+        }
+        Commands::Up { dry_run } => {
+            deploy::up(dry_run)?;
+        }
+        Commands::Registry { command } => match command {
+            RegistryCommands::Setup { url } => {
+                registry::setup(url)?;
+            }
+        },
+        Commands::Rollback { to } => {
+            rollback::rollback(to)?;
         }
-        Commands::Launch => {
-            launch::launch()?;
+        Commands::Logs {
+            process,
+            follow,
+            since,
+            tail,
+        } => {
+            logs::logs(process, follow, since, tail)?;
         }
-        Commands::Deploy => {
-            deploy::deploy()?;
+        Commands::Status { json } => {
+            status::status(json)?;
+        }
+        Commands::Destroy { volumes, yes } => {
+            destroy::destroy(volumes, yes)?;
+        }
+        Commands::Prune { keep } => {
+            prune::prune(keep)?;
+        }
+        Commands::Exec { process, command } => {
+            exit_with(exec::exec(process, command)?);
+        }
+        Commands::Jobs { command } => match command {
+            JobsCommands::List => {
+                jobs::list()?;
+            }
+            JobsCommands::Run { name } => {
+                exit_with(jobs::run(name)?);
+            }
+        },
+        Commands::Shell { process } => {
+            exit_with(exec::shell(process)?);
         }
     }
     Ok(())
 }
+
+fn exit_with(code: i32) {
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
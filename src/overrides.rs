@@ -0,0 +1,213 @@
+use crate::config::AppConfig;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+
+/// Compose settings hobby does not generate itself, merged over the
+/// generated compose file. Either a path to a YAML file or an inline mapping.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ComposeOverride {
+    File(String),
+    Inline(Mapping),
+}
+
+impl ComposeOverride {
+    pub fn load(&self) -> Result<Mapping> {
+        match self {
+            ComposeOverride::Inline(mapping) => Ok(mapping.clone()),
+            ComposeOverride::File(path) => {
+                let data = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read compose override {}", path))?;
+                match serde_yaml::from_str(&data)
+                    .with_context(|| format!("Failed to parse compose override {}", path))?
+                {
+                    Value::Mapping(mapping) => Ok(mapping),
+                    Value::Null => Ok(Mapping::new()),
+                    _ => Err(anyhow!("Compose override {} is not a mapping", path)),
+                }
+            }
+        }
+    }
+}
+
+/// Deep-merges the app's compose override into the generated compose file.
+/// Mappings are merged key by key, anything else in the override replaces
+/// the generated value, and `null` removes it. Returns one line per
+/// generated value that was replaced or removed.
+pub fn apply(config: &AppConfig, compose: &mut Value) -> Result<Vec<String>> {
+    let Some(compose_override) = &config.compose_override else {
+        return Ok(Vec::new());
+    };
+
+    let mut conflicts = Vec::new();
+    merge(
+        compose,
+        Value::Mapping(compose_override.load()?),
+        &mut Vec::new(),
+        &mut conflicts,
+    );
+    Ok(conflicts)
+}
+
+fn merge(base: &mut Value, overlay: Value, path: &mut Vec<String>, conflicts: &mut Vec<String>) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                path.push(key_name(&key));
+                if value.is_null() {
+                    if let Some(removed) = base.remove(&key) {
+                        conflicts.push(format!("{}: {} removed", path.join("."), inline(&removed)));
+                    }
+                } else {
+                    match base.get_mut(&key) {
+                        Some(existing) => merge(existing, value, path, conflicts),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+                path.pop();
+            }
+        }
+        (base, overlay) => {
+            if *base != overlay {
+                conflicts.push(format!(
+                    "{}: {} replaced with {}",
+                    path.join("."),
+                    inline(base),
+                    inline(&overlay)
+                ));
+            }
+            *base = overlay;
+        }
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => inline(other),
+    }
+}
+
+/// A value on a single line, for the conflict report.
+fn inline(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "?".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(value: &str) -> Value {
+        serde_yaml::from_str(value).unwrap()
+    }
+
+    fn merged(base: &str, overlay: &str) -> (Value, Vec<String>) {
+        let mut base = yaml(base);
+        let mut conflicts = Vec::new();
+        merge(&mut base, yaml(overlay), &mut Vec::new(), &mut conflicts);
+        (base, conflicts)
+    }
+
+    #[test]
+    fn merges_mappings_key_by_key() {
+        let (result, conflicts) = merged(
+            "services: {app: {image: app:V1, restart: unless-stopped}}",
+            "services: {app: {extra_hosts: [host.docker.internal:host-gateway]}, cron: {image: busybox}}",
+        );
+        assert_eq!(
+            result,
+            yaml(
+                "services:
+                  app: {image: app:V1, restart: unless-stopped, extra_hosts: [host.docker.internal:host-gateway]}
+                  cron: {image: busybox}"
+            )
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn replaces_other_values_and_reports_them() {
+        let (result, conflicts) = merged(
+            "services: {app: {restart: unless-stopped, networks: [caddy]}}",
+            "services: {app: {restart: always, networks: [caddy, other]}}",
+        );
+        assert_eq!(
+            result,
+            yaml("services: {app: {restart: always, networks: [caddy, other]}}")
+        );
+        assert_eq!(
+            conflicts,
+            vec![
+                r#"services.app.restart: "unless-stopped" replaced with "always""#,
+                r#"services.app.networks: ["caddy"] replaced with ["caddy","other"]"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_values_set_to_null() {
+        let (result, conflicts) = merged(
+            "services: {app: {image: app:V1, labels: {caddy: example.com}}}",
+            "services: {app: {labels: null, healthcheck: null}}",
+        );
+        assert_eq!(result, yaml("services: {app: {image: app:V1}}"));
+        assert_eq!(
+            conflicts,
+            vec![r#"services.app.labels: {"caddy":"example.com"} removed"#]
+        );
+    }
+
+    #[test]
+    fn unchanged_values_are_not_conflicts() {
+        let (_, conflicts) = merged(
+            "services: {app: {restart: unless-stopped}}",
+            "services: {app: {restart: unless-stopped}}",
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn applies_the_configured_override() {
+        let mut config = AppConfig::default();
+        let mut compose = yaml("services: {app: {restart: unless-stopped}}");
+        assert!(apply(&config, &mut compose).unwrap().is_empty());
+
+        config.compose_override =
+            Some(serde_yaml::from_str("services: {app: {restart: always}}").unwrap());
+        let conflicts = apply(&config, &mut compose).unwrap();
+        assert_eq!(compose, yaml("services: {app: {restart: always}}"));
+        assert_eq!(conflicts.len(), 1);
+    }
+
+    #[test]
+    fn loads_overrides_from_files() {
+        let dir = std::env::temp_dir().join(format!("hobby-overrides-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mapping = dir.join("override.yml");
+        let list = dir.join("list.yml");
+        fs::write(&mapping, "services: {app: {init: true}}").unwrap();
+        fs::write(&list, "- not a mapping").unwrap();
+
+        let loaded = ComposeOverride::File(mapping.to_string_lossy().to_string())
+            .load()
+            .unwrap();
+        assert_eq!(
+            Value::Mapping(loaded),
+            yaml("services: {app: {init: true}}")
+        );
+        assert!(ComposeOverride::File(list.to_string_lossy().to_string())
+            .load()
+            .is_err());
+        assert!(
+            ComposeOverride::File(dir.join("missing.yml").to_string_lossy().to_string())
+                .load()
+                .is_err()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config::{load_app_config, parse_version, AppConfig};
+use crate::docker;
+use crate::runner::{Runner, SshRunner};
+use crate::terminal::format_bytes;
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+/// Image versions kept by `hobby prune` when hobby.yml sets no `keep_images`.
+pub const DEFAULT_KEEP_IMAGES: usize = 5;
+
+/// Removes the app's images beyond the newest `keep` versions and prints the
+/// disk space their removal freed.
+pub fn prune(keep: Option<usize>) -> Result<()> {
+    let app_config = load_app_config()?;
+    let keep = keep
+        .or(app_config.keep_images)
+        .unwrap_or(DEFAULT_KEEP_IMAGES);
+    let runner = SshRunner::connect(&app_config)?;
+
+    let before = image_disk_usage(&runner)?;
+    let removed = remove_old_images(&runner, &app_config, keep)?;
+    if removed.is_empty() {
+        info!("No image versions older than the newest {} to remove", keep);
+        return Ok(());
+    }
+    let after = image_disk_usage(&runner)?;
+
+    info!(
+        "Removed {} and reclaimed {}",
+        removed.join(", "),
+        format_bytes(before.saturating_sub(after))
+    );
+    Ok(())
+}
+
+/// Bytes the server's image layers take on disk. Layers shared between images
+/// are counted once, so the difference around a removal is the space it freed.
+fn image_disk_usage(runner: &dyn Runner) -> Result<u64> {
+    let output = runner
+        .query_remote("curl -sf --unix-socket /var/run/docker.sock http://localhost/system/df")?;
+    let usage: serde_json::Value = serde_json::from_str(&output)
+        .map_err(|e| anyhow!("Failed to read Docker disk usage: {}", e))?;
+    usage["LayersSize"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("Docker disk usage has no layer size"))
+}
+
+/// Removes the app's image versions beyond the newest `keep`. The running
+/// version and the one `hobby rollback` would switch to are always kept,
+/// even when they are older. Returns the removed versions.
+pub fn remove_old_images(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    keep: usize,
+) -> Result<Vec<String>> {
+    if keep == 0 {
+        return Err(anyhow!("keep_images must be at least 1"));
+    }
+
+    let versions = docker::remote_image_versions(runner, config)?;
+    let running = docker::running_image_version(runner, config).ok();
+
+    let mut kept: Vec<&String> = versions.iter().rev().take(keep).collect();
+    if let Some(running) = &running {
+        kept.push(running);
+        if let Ok(running_number) = parse_version(running) {
+            let rollback_target = versions
+                .iter()
+                .rev()
+                .find(|v| parse_version(v).is_ok_and(|n| n < running_number));
+            kept.extend(rollback_target);
+        }
+    }
+
+    let versions: Vec<String> = versions
+        .iter()
+        .filter(|version| !kept.contains(version))
+        .cloned()
+        .collect();
+    if versions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let repository = docker::image_repository(config);
+    let tags: Vec<String> = versions
+        .iter()
+        .map(|version| format!("{}:{}", repository, version))
+        .collect();
+    info!("Removing old images {}...", versions.join(", "));
+    runner.run_remote(&[&format!("docker image rm {}", tags.join(" "))])?;
+    Ok(versions)
+}
@@ -0,0 +1,191 @@
+use crate::commands::{connect_app, shell_quote};
+use crate::config::{
+    self, generate_secret, get_config_dir, load_app_config, AppConfig, RegistryConfig,
+};
+use crate::docker::{self, DockerComposeFile, DockerNetwork, DockerService, DockerVolume};
+use crate::runner::{Runner, SshRunner};
+use crate::volumes::VolumeConfig;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use tracing::info;
+
+pub const DEFAULT_PASSWORD_ENV: &str = "HOBBY_REGISTRY_PASSWORD";
+
+/// User created in the htpasswd file of a registry run by `hobby registry setup`.
+const REGISTRY_USER: &str = "hobby";
+
+fn password_path(url: &str) -> Result<PathBuf> {
+    Ok(get_config_dir()?
+        .join("registries")
+        .join(url.replace([':', '/'], "_")))
+}
+
+pub fn password(registry: &RegistryConfig) -> Result<String> {
+    let env = registry
+        .password_env
+        .as_deref()
+        .unwrap_or(DEFAULT_PASSWORD_ENV);
+    if let Ok(password) = std::env::var(env) {
+        return Ok(password);
+    }
+
+    fs::read_to_string(password_path(&registry.url)?)
+        .map(|password| password.trim().to_string())
+        .map_err(|_| {
+            anyhow!(
+                "No password for registry {}, set {} or run `hobby registry setup`",
+                registry.url,
+                env
+            )
+        })
+}
+
+/// Pushes the freshly built image and pulls it on the server. Passwords are
+/// passed on stdin and the server is logged out again right after the pull.
+pub fn push_and_pull_image(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    registry: &RegistryConfig,
+) -> Result<()> {
+    let image = docker::image_tag(config);
+
+    let Some(username) = &registry.username else {
+        runner.run_local(&format!("docker push {}", image))?;
+        return runner.run_remote(&[&format!("docker pull {}", image)]);
+    };
+
+    let password = password(registry)?;
+    let login = format!(
+        "docker login {} --username {} --password-stdin",
+        registry.url,
+        shell_quote(username)
+    );
+
+    info!("Pushing {} to {}...", image, registry.url);
+    runner.run_local_with_input(&login, password.as_bytes())?;
+    runner.run_local(&format!("docker push {}", image))?;
+
+    info!("Pulling {} on the server...", image);
+    runner.run_remote_with_input(&login, password.as_bytes())?;
+    let pulled = runner.run_remote(&[&format!("docker pull {}", image)]);
+    runner.run_remote(&[&format!("docker logout {}", registry.url)])?;
+    pulled
+}
+
+/// Runs a `registry:2` behind Caddy on the app's server, protected by a
+/// generated password, and points hobby.yml at it.
+pub fn setup(url: Option<String>) -> Result<()> {
+    let mut app_config = load_app_config()?;
+    let url = url.unwrap_or_else(|| format!("registry.{}.sslip.io", app_config.server));
+
+    let runner = SshRunner::from_session(connect_app(&app_config)?);
+    let password = generate_secret()?;
+
+    info!("Creating registry credentials...");
+    runner.run_remote(&["mkdir -p registry/auth"])?;
+    runner.run_remote_with_input(
+        &format!(
+            "docker run --rm -i --entrypoint htpasswd httpd:2 -Bin {} > registry/auth/htpasswd",
+            REGISTRY_USER
+        ),
+        password.as_bytes(),
+    )?;
+
+    info!("Starting registry at {}...", url);
+    let compose = serde_yaml::to_string(&build_registry_compose(&url))?;
+    runner.upload_bytes(compose.as_bytes(), "registry/docker-compose.yaml", 0o644)?;
+    runner.run_remote(&["cd registry && docker compose -p registry up -d"])?;
+
+    save_password(&url, &password)?;
+
+    app_config.registry = Some(RegistryConfig {
+        url: url.clone(),
+        username: Some(REGISTRY_USER.to_string()),
+        password_env: None,
+    });
+    config::save_application_config(&app_config)?;
+
+    info!("Registry is available at https://{}", url);
+    info!("hobby.yml now deploys through the registry");
+    Ok(())
+}
+
+fn build_registry_compose(url: &str) -> DockerComposeFile {
+    let mut labels = HashMap::new();
+    labels.insert("caddy".to_string(), url.to_string());
+    labels.insert(
+        "caddy.reverse_proxy".to_string(),
+        "{{upstreams 5000}}".to_string(),
+    );
+
+    let mut environment = HashMap::new();
+    environment.insert("REGISTRY_AUTH".to_string(), "htpasswd".to_string());
+    environment.insert(
+        "REGISTRY_AUTH_HTPASSWD_REALM".to_string(),
+        "hobby".to_string(),
+    );
+    environment.insert(
+        "REGISTRY_AUTH_HTPASSWD_PATH".to_string(),
+        "/auth/htpasswd".to_string(),
+    );
+    environment.insert(
+        "REGISTRY_STORAGE_DELETE_ENABLED".to_string(),
+        "true".to_string(),
+    );
+
+    let service = DockerService {
+        image: "registry:2".to_string(),
+        restart: "unless-stopped".to_string(),
+        command: None,
+        labels,
+        environment: Some(environment),
+        volumes: Some(vec![
+            VolumeConfig::Short("registry_data:/var/lib/registry".to_string()),
+            VolumeConfig::Short("./auth:/auth:ro".to_string()),
+        ]),
+        networks: vec!["caddy".to_string()],
+        healthcheck: None,
+        mem_limit: None,
+        cpus: None,
+        pids_limit: None,
+        profiles: None,
+    };
+
+    let mut services = HashMap::new();
+    services.insert("registry".to_string(), service);
+    let mut networks = HashMap::new();
+    networks.insert(
+        "caddy".to_string(),
+        DockerNetwork {
+            external: true,
+            internal: None,
+        },
+    );
+    let mut volumes = HashMap::new();
+    volumes.insert("registry_data".to_string(), DockerVolume::default());
+
+    DockerComposeFile {
+        services,
+        networks,
+        volumes: Some(volumes),
+    }
+}
+
+fn save_password(url: &str, password: &str) -> Result<()> {
+    let path = password_path(url)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(password.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use crate::config::{load_app_config, parse_version, AppConfig};
+use crate::docker;
+use crate::runner::{Runner, SshRunner};
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+pub fn rollback(to: Option<String>) -> Result<()> {
+    let start = std::time::Instant::now();
+
+    info!("Loading app config...");
+    let mut app_config = load_app_config()?;
+
+    let runner = SshRunner::connect(&app_config)?;
+
+    let versions = docker::remote_image_versions(&runner, &app_config)?;
+    let current = docker::running_image_version(&runner, &app_config)?;
+
+    let target = match to {
+        Some(version) => {
+            if !versions.contains(&version) {
+                return Err(anyhow!(
+                    "Version {} is not available on the server (available: {})",
+                    version,
+                    versions.join(", ")
+                ));
+            }
+            version
+        }
+        None => {
+            let current_number = parse_version(&current)?;
+            versions
+                .iter()
+                .rev()
+                .find(|v| parse_version(v).is_ok_and(|n| n < current_number))
+                .cloned()
+                .ok_or_else(|| anyhow!("No version older than {} on the server", current))?
+        }
+    };
+
+    if target == current {
+        info!("{} is already running, nothing to do", current);
+        return Ok(());
+    }
+
+    info!("Rolling back from {} to {}...", current, target);
+
+    switch_version(&runner, &mut app_config, target)?;
+
+    info!(
+        "Rollback to {} completed successfully in {:?}",
+        app_config.version,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
+/// Restarts the app on an image version that is already on the server. Only
+/// the image tag in the generated compose file changes, hobby.yml keeps
+/// tracking the latest deployed version.
+fn switch_version(runner: &dyn Runner, config: &mut AppConfig, version: String) -> Result<()> {
+    config.version = version;
+
+    let compose = docker::build_compose_config(config)?;
+    docker::upload_compose_file(runner, config, &compose)?;
+
+    runner.run_remote(&[&docker::compose_up_command(config)?])
+}
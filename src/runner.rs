@@ -0,0 +1,218 @@
+use crate::commands::{
+    connect_app, redact_secrets, run_ssh_command_output, run_ssh_command_with_input,
+    run_ssh_command_with_writer, run_ssh_commands,
+};
+use crate::config::AppConfig;
+use anyhow::{anyhow, Result};
+use ssh2::{FileStat, OpenFlags, OpenType, Session};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Everything `up` does to the local machine or the server goes through a
+/// runner, so it can be swapped for [`DryRun`] to review the plan without
+/// touching production.
+pub trait Runner {
+    fn run_local(&self, cmd: &str) -> Result<()>;
+    fn run_local_output(&self, cmd: &str) -> Result<Vec<u8>>;
+    /// Runs a local command with `input` on its stdin, which is never printed.
+    fn run_local_with_input(&self, cmd: &str, input: &[u8]) -> Result<()>;
+    fn run_remote(&self, commands: &[&str]) -> Result<()>;
+    /// Runs a remote command with `input` on its stdin, which is never printed.
+    fn run_remote_with_input(&self, cmd: &str, input: &[u8]) -> Result<()>;
+    /// Runs a read-only remote command and returns its output. Queries also
+    /// run during a dry run since their results decide what the plan is.
+    fn query_remote(&self, cmd: &str) -> Result<String>;
+    /// Uploads `data` to `remote_path`, relative to the remote home directory.
+    fn upload_bytes(&self, data: &[u8], remote_path: &str, mode: i32) -> Result<()>;
+    /// Runs a remote command whose stdin is produced by `write` as it goes.
+    /// `source` describes the data for the dry run output.
+    fn stream_to_remote(
+        &self,
+        source: &str,
+        cmd: &str,
+        write: &mut dyn FnMut(&mut dyn Write) -> Result<()>,
+    ) -> Result<()>;
+}
+
+pub struct SshRunner {
+    session: Session,
+}
+
+impl SshRunner {
+    pub fn connect(config: &AppConfig) -> Result<SshRunner> {
+        Ok(SshRunner {
+            session: connect_app(config)?,
+        })
+    }
+
+    pub fn from_session(session: Session) -> SshRunner {
+        SshRunner { session }
+    }
+
+    /// Streams `reader` into a temporary file next to `remote_path` and moves
+    /// it into place once complete, so readers never see a partial file.
+    fn upload(&self, reader: &mut dyn Read, remote_path: &str, mode: i32) -> Result<()> {
+        let sftp = self.session.sftp()?;
+        let temp_path = format!("{}.hobby-upload", remote_path);
+
+        let mut file = sftp.open_mode(
+            Path::new(&temp_path),
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            mode,
+            OpenType::File,
+        )?;
+        io::copy(reader, &mut file)
+            .map_err(|e| anyhow!("Failed to upload {}: {}", remote_path, e))?;
+        file.setstat(FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(mode as u32),
+            atime: None,
+            mtime: None,
+        })?;
+        drop(file);
+
+        // SFTP v3 renames refuse to replace an existing file, mv uses rename(2).
+        run_ssh_commands(
+            &self.session,
+            &[&format!("mv -f {} {}", temp_path, remote_path)],
+        )
+    }
+}
+
+impl Runner for SshRunner {
+    fn run_local(&self, cmd: &str) -> Result<()> {
+        self.run_local_output(cmd)?;
+        Ok(())
+    }
+
+    fn run_local_output(&self, cmd: &str) -> Result<Vec<u8>> {
+        run_local_command(cmd, &[])
+    }
+
+    fn run_local_with_input(&self, cmd: &str, input: &[u8]) -> Result<()> {
+        run_local_command(cmd, input)?;
+        Ok(())
+    }
+
+    fn run_remote(&self, commands: &[&str]) -> Result<()> {
+        run_ssh_commands(&self.session, commands)
+    }
+
+    fn run_remote_with_input(&self, cmd: &str, input: &[u8]) -> Result<()> {
+        run_ssh_command_with_input(&self.session, cmd, input)?;
+        Ok(())
+    }
+
+    fn query_remote(&self, cmd: &str) -> Result<String> {
+        run_ssh_command_output(&self.session, cmd)
+    }
+
+    fn upload_bytes(&self, mut data: &[u8], remote_path: &str, mode: i32) -> Result<()> {
+        self.upload(&mut data, remote_path, mode)
+    }
+
+    fn stream_to_remote(
+        &self,
+        _source: &str,
+        cmd: &str,
+        write: &mut dyn FnMut(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        run_ssh_command_with_writer(&self.session, cmd, write)?;
+        Ok(())
+    }
+}
+
+/// Prints every command and generated file instead of executing anything.
+/// Only read-only queries are sent to the server.
+pub struct DryRun {
+    session: Session,
+}
+
+impl DryRun {
+    pub fn connect(config: &AppConfig) -> Result<DryRun> {
+        Ok(DryRun {
+            session: connect_app(config)?,
+        })
+    }
+}
+
+impl Runner for DryRun {
+    fn run_local(&self, cmd: &str) -> Result<()> {
+        println!("[local]  {}", redact_secrets(cmd));
+        Ok(())
+    }
+
+    fn run_local_output(&self, cmd: &str) -> Result<Vec<u8>> {
+        self.run_local(cmd)?;
+        Ok(Vec::new())
+    }
+
+    fn run_local_with_input(&self, cmd: &str, _input: &[u8]) -> Result<()> {
+        println!("[local]  {} <<< (hidden)", redact_secrets(cmd));
+        Ok(())
+    }
+
+    fn run_remote(&self, commands: &[&str]) -> Result<()> {
+        for cmd in commands {
+            println!("[remote] {}", redact_secrets(cmd));
+        }
+        Ok(())
+    }
+
+    fn run_remote_with_input(&self, cmd: &str, _input: &[u8]) -> Result<()> {
+        println!("[remote] {} <<< (hidden)", redact_secrets(cmd));
+        Ok(())
+    }
+
+    fn query_remote(&self, cmd: &str) -> Result<String> {
+        println!("[query]  {}", redact_secrets(cmd));
+        run_ssh_command_output(&self.session, cmd)
+    }
+
+    fn upload_bytes(&self, data: &[u8], remote_path: &str, mode: i32) -> Result<()> {
+        println!("[upload] {} ({:o})", remote_path, mode);
+        if let Ok(contents) = std::str::from_utf8(data) {
+            for line in contents.lines() {
+                println!("         {}", line);
+            }
+        }
+        Ok(())
+    }
+
+    fn stream_to_remote(
+        &self,
+        source: &str,
+        cmd: &str,
+        _write: &mut dyn FnMut(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        println!("[stream] {} | {}", source, redact_secrets(cmd));
+        Ok(())
+    }
+}
+
+fn run_local_command(cmd: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Command failed: {}\nOutput: {}\nError: {}",
+            redact_secrets(cmd),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -3,22 +3,28 @@ use anyhow::Result;
 use homedir::my_home;
 use ssh2::Session;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
-use crate::commands::{connect_ssh, run_ssh_commands};
+use crate::commands::{connect_ssh, run_ssh_command_output, run_ssh_commands};
+use crate::config::SshConfig;
+use crate::docker;
 
-pub fn setup(server_addr: String) -> Result<()> {
-    let root_session = connect_ssh("root", &server_addr)?;
-    setup_hobby_user(&root_session)?;
-    let ssh_session = connect_ssh("hobby", &server_addr)?;
-    configure_server(&ssh_session)?;
+pub fn setup(server_addr: String, ssh: SshConfig) -> Result<()> {
+    let root_session = connect_ssh("root", &server_addr, &ssh)?;
+    setup_hobby_user(&root_session, ssh.user())?;
+    let ssh_session = connect_ssh(ssh.user(), &server_addr, &ssh)?;
+    let arch = run_ssh_command_output(&ssh_session, docker::DETECT_ARCH_COMMAND)?
+        .trim()
+        .to_string();
+    info!("Detected server architecture: {}", arch);
+    configure_server(&ssh_session, ssh.user(), &arch)?;
 
     let config_dir = create_config_directory()?;
 
     create_age_keys_if_not_exist(&config_dir)?;
 
-    save_default_config(&server_addr)?;
+    save_default_config(&server_addr, &arch, &ssh)?;
     print_success_message();
     Ok(())
 }
@@ -32,31 +38,41 @@ fn create_config_directory() -> Result<PathBuf> {
     Ok(config_dir)
 }
 
-fn save_default_config(server_addr: &str) -> Result<()> {
+fn save_default_config(server_addr: &str, arch: &str, ssh: &SshConfig) -> Result<()> {
     let default_url = format!("my-app.{}.sslip.io", server_addr);
-    let conf = format!(
+    let mut conf = format!(
         r#"
 name: "myapp"
 version: "V0"
 port: 8080
 server: {}
 url: {}
+arch: {}
+#platform: linux/amd64
 #env:
 #  file: .env
 #  hash: ""
 #volumes:
 #  - dbdata:/app/db/
 "#,
-        server_addr, default_url
+        server_addr, default_url, arch
     );
 
+    if ssh.port.is_some() || ssh.identity_file.is_some() || ssh.user.is_some() {
+        #[derive(serde::Serialize)]
+        struct SshSection<'a> {
+            ssh: &'a SshConfig,
+        }
+        conf.push_str(&serde_yaml::to_string(&SshSection { ssh })?);
+    }
+
     fs::write("hobby.yml", conf.as_bytes())
         .map_err(|e| anyhow::anyhow!("Failed to write config file: {}", e))?;
 
     Ok(())
 }
 
-fn create_age_keys_if_not_exist(config_dir: &PathBuf) -> Result<()> {
+fn create_age_keys_if_not_exist(config_dir: &Path) -> Result<()> {
     let public_key_path = config_dir.join("key.pub");
     let secret_key_path = config_dir.join("key.txt");
 
@@ -80,30 +96,31 @@ fn create_age_keys_if_not_exist(config_dir: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn setup_hobby_user(session: &Session) -> Result<()> {
-    info!("Setting up hobby user...");
+fn setup_hobby_user(session: &Session, user: &str) -> Result<()> {
+    info!("Setting up {} user...", user);
 
-    let commands = vec![
-        "id hobby || useradd -m -s /bin/bash -G sudo hobby",
-        "echo \"hobby ALL=(ALL) NOPASSWD: ALL\" >> /etc/sudoers.d/hobby",
-        "mkdir -p /home/hobby/.ssh/",
-        "cat /root/.ssh/authorized_keys | tee -a /home/hobby/.ssh/authorized_keys",
-        "chown hobby:hobby /home/hobby/.ssh/authorized_keys",
-        "chmod 600 /home/hobby/.ssh/authorized_keys",
+    let commands = [
+        format!("id {user} || useradd -m -s /bin/bash -G sudo {user}"),
+        format!("echo \"{user} ALL=(ALL) NOPASSWD: ALL\" >> /etc/sudoers.d/{user}"),
+        format!("mkdir -p /home/{user}/.ssh/"),
+        format!("cat /root/.ssh/authorized_keys | tee -a /home/{user}/.ssh/authorized_keys"),
+        format!("chown {user}:{user} /home/{user}/.ssh/authorized_keys"),
+        format!("chmod 600 /home/{user}/.ssh/authorized_keys"),
     ];
 
+    let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
     run_ssh_commands(session, &commands)?;
-    info!("Hobby user setup completed successfully");
+    info!("{} user setup completed successfully", user);
     Ok(())
 }
 
-fn configure_server(session: &Session) -> Result<()> {
+fn configure_server(session: &Session, user: &str, arch: &str) -> Result<()> {
     info!("Configuring server...");
 
     info!("Setting up basic system...");
-    setup_basic_system(session)?;
+    setup_basic_system(session, arch)?;
     info!("Setting up Docker...");
-    setup_docker(session)?;
+    setup_docker(session, user)?;
     info!("Setting up Caddy...");
     setup_caddy(session)?;
 
@@ -111,22 +128,30 @@ fn configure_server(session: &Session) -> Result<()> {
     Ok(())
 }
 
-fn setup_basic_system(session: &Session) -> Result<()> {
+fn setup_basic_system(session: &Session, arch: &str) -> Result<()> {
+    let sops_arch = if arch == "arm64" { "arm64" } else { "amd64" };
+    let download_sops = format!(
+        "curl -LO \"https://github.com/getsops/sops/releases/download/v3.9.1/sops-v3.9.1.linux.{}\"",
+        sops_arch
+    );
+    let install_sops = format!(
+        "sudo mv \"sops-v3.9.1.linux.{}\" /usr/local/bin/sops",
+        sops_arch
+    );
     let commands = vec![
         "sudo sed -i 's/#PermitRootLogin prohibit-password/PermitRootLogin no/' /etc/ssh/sshd_config && sudo systemctl restart ssh",
         "sudo apt-get update -y",
         "sudo apt-get upgrade -y",
-        "sudo apt-get install age ca-certificates curl vim -y",
-        "ARCH=$(dpkg --print-architecture)",
-        "if [ \"$ARCH\" = \"arm64\" ]; then SOPS_ARCH=arm64; else SOPS_ARCH=amd64; fi",
-        "curl -LO \"https://github.com/getsops/sops/releases/download/v3.9.1/sops-v3.9.1.linux.$SOPS_ARCH\"",
-        "sudo mv \"sops-v3.9.1.linux.$SOPS_ARCH\" /usr/local/bin/sops",
+        "sudo apt-get install age ca-certificates cron curl vim -y",
+        &download_sops,
+        &install_sops,
         "sudo chmod +x /usr/local/bin/sops",
     ];
     run_ssh_commands(session, &commands)
 }
 
-fn setup_docker(session: &Session) -> Result<()> {
+fn setup_docker(session: &Session, user: &str) -> Result<()> {
+    let add_to_docker_group = format!("sudo usermod -aG docker {}", user);
     let commands = vec![
     	"sudo apt-get update -y",
     	"sudo install -m 0755 -d /etc/apt/keyrings",
@@ -135,7 +160,7 @@ fn setup_docker(session: &Session) -> Result<()> {
     	"echo \"deb [arch=$(dpkg --print-architecture) signed-by=/etc/apt/keyrings/docker.asc] https://download.docker.com/linux/ubuntu $(. /etc/os-release && echo \"$VERSION_CODENAME\") stable\" | sudo tee /etc/apt/sources.list.d/docker.list > /dev/null",
     	"sudo apt-get update -y",
     	"sudo apt-get install docker-ce docker-ce-cli containerd.io docker-buildx-plugin docker-compose-plugin -y",
-    	"sudo usermod -aG docker hobby",
+    	&add_to_docker_group,
 	];
     run_ssh_commands(session, &commands)
 }
@@ -176,7 +201,7 @@ volumes:
     let commands = vec![
         "sudo docker network create caddy",
         &docker_compose_command,
-        "cd caddy && sudo docker compose -p hobby -f docker-compose.caddy.yml up -d",
+        "cd caddy && sudo docker compose -p caddy -f docker-compose.caddy.yml up -d",
     ];
 
     // Run the commands
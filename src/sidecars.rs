@@ -0,0 +1,331 @@
+use crate::config::{generate_secret, AppConfig, EnvConfig};
+use crate::docker::{DockerHealthcheck, DockerNetwork, DockerService, DockerVolume};
+use crate::volumes::VolumeConfig;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use tracing::info;
+
+const POSTGRES_PASSWORD_VAR: &str = "POSTGRES_PASSWORD";
+const REDIS_PASSWORD_VAR: &str = "REDIS_PASSWORD";
+
+/// Env file created for the generated passwords when hobby.yml has none.
+const DEFAULT_ENV_FILE: &str = ".env";
+
+/// Name of the network the app shares with its sidecars. It is internal, so
+/// the databases are unreachable from outside and from other apps.
+pub fn network_name(config: &AppConfig) -> String {
+    format!("{}-internal", config.name)
+}
+
+fn postgres_service_name(config: &AppConfig) -> String {
+    format!("{}-postgres", config.name)
+}
+
+fn redis_service_name(config: &AppConfig) -> String {
+    format!("{}-redis", config.name)
+}
+
+/// Compose service names of the configured sidecars.
+pub fn service_names(config: &AppConfig) -> Vec<String> {
+    let mut names = Vec::new();
+    let Some(sidecars) = &config.services else {
+        return names;
+    };
+    if sidecars.postgres.is_some() {
+        names.push(postgres_service_name(config));
+    }
+    if sidecars.redis.is_some() {
+        names.push(redis_service_name(config));
+    }
+    names
+}
+
+fn postgres_volume_name(config: &AppConfig) -> String {
+    format!("{}-postgres-data", config.name)
+}
+
+fn redis_volume_name(config: &AppConfig) -> String {
+    format!("{}-redis-data", config.name)
+}
+
+/// Names of the named volumes the sidecars keep their data in.
+pub fn volume_names(config: &AppConfig) -> Vec<String> {
+    let mut volumes = Vec::new();
+    let Some(sidecars) = &config.services else {
+        return volumes;
+    };
+    if sidecars.postgres.is_some() {
+        volumes.push(postgres_volume_name(config));
+    }
+    if sidecars.redis.is_some() {
+        volumes.push(redis_volume_name(config));
+    }
+    volumes
+}
+
+/// Postgres user and database name, derived from the app name.
+fn database_name(config: &AppConfig) -> String {
+    config.name.replace('-', "_")
+}
+
+/// Makes sure the env file holds a password for every sidecar, generating
+/// the missing ones. They reach the server with the rest of the encrypted
+/// env file and are never written to hobby.yml.
+pub fn ensure_passwords(config: &mut AppConfig, dry_run: bool) -> Result<()> {
+    ensure_passwords_in(config, DEFAULT_ENV_FILE, dry_run)
+}
+
+fn ensure_passwords_in(config: &mut AppConfig, default_file: &str, dry_run: bool) -> Result<()> {
+    let Some(sidecars) = &config.services else {
+        return Ok(());
+    };
+    let mut required = Vec::new();
+    if sidecars.postgres.is_some() {
+        required.push(POSTGRES_PASSWORD_VAR);
+    }
+    if sidecars.redis.is_some() {
+        required.push(REDIS_PASSWORD_VAR);
+    }
+
+    let file = match &config.env {
+        Some(env) if !env.file.is_empty() => env.file.clone(),
+        _ => default_file.to_string(),
+    };
+    let existing = fs::read_to_string(&file).unwrap_or_default();
+    let missing: Vec<&str> = required
+        .into_iter()
+        .filter(|var| !env_file_defines(&existing, var))
+        .collect();
+    if missing.is_empty() {
+        // The passwords may come from an earlier run that failed before
+        // hobby.yml was saved, or were written by hand.
+        use_env_file(config, file);
+        return Ok(());
+    }
+
+    if dry_run {
+        info!("Would generate {} in {}", missing.join(", "), file);
+        return Ok(());
+    }
+
+    info!("Generating {} in {}...", missing.join(", "), file);
+    let mut env_file = OpenOptions::new().create(true).append(true).open(&file)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(env_file)?;
+    }
+    for var in missing {
+        writeln!(env_file, "{}={}", var, generate_secret()?)?;
+    }
+
+    use_env_file(config, file);
+    Ok(())
+}
+
+/// Points the config at `file` when it has no env file yet, so it is
+/// encrypted, uploaded and passed to compose.
+fn use_env_file(config: &mut AppConfig, file: String) {
+    if config.env.as_ref().is_none_or(|env| env.file.is_empty()) {
+        config.env = Some(EnvConfig {
+            file,
+            hash: String::new(),
+        });
+    }
+}
+
+fn env_file_defines(contents: &str, var: &str) -> bool {
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, _)| key.trim() == var)
+}
+
+/// Adds the sidecar services, their volumes and the internal network to the
+/// compose file, and hands the app its connection URLs. Variables the env
+/// file already defines are left alone.
+pub fn add_sidecars(
+    config: &AppConfig,
+    services: &mut HashMap<String, DockerService>,
+    networks: &mut HashMap<String, DockerNetwork>,
+    volumes: &mut HashMap<String, DockerVolume>,
+    app_environment: &mut HashMap<String, String>,
+) -> Result<()> {
+    let Some(sidecars) = &config.services else {
+        return Ok(());
+    };
+    for process in config.processes.iter().flat_map(|p| p.keys()) {
+        if process == "postgres" || process == "redis" {
+            return Err(anyhow!(
+                "Process '{}' collides with the {} service, rename it",
+                process,
+                process
+            ));
+        }
+    }
+
+    let network = network_name(config);
+
+    if let Some(postgres) = &sidecars.postgres {
+        let name = postgres_service_name(config);
+        let volume = postgres_volume_name(config);
+        let database = database_name(config);
+
+        let mut environment = HashMap::new();
+        environment.insert("POSTGRES_USER".to_string(), database.clone());
+        environment.insert("POSTGRES_DB".to_string(), database.clone());
+        environment.insert(
+            POSTGRES_PASSWORD_VAR.to_string(),
+            format!("${{{}}}", POSTGRES_PASSWORD_VAR),
+        );
+
+        services.insert(
+            name.clone(),
+            sidecar_service(
+                format!("postgres:{}", postgres.version.as_deref().unwrap_or("16")),
+                None,
+                Some(environment),
+                format!("{}:/var/lib/postgresql/data", volume),
+                format!("pg_isready -U {}", database),
+                &network,
+            ),
+        );
+        volumes.insert(volume, DockerVolume::default());
+        app_environment
+            .entry("DATABASE_URL".to_string())
+            .or_insert(format!(
+                "postgres://{db}:${{{}}}@{}:5432/{db}",
+                POSTGRES_PASSWORD_VAR,
+                name,
+                db = database
+            ));
+    }
+
+    if let Some(redis) = &sidecars.redis {
+        let name = redis_service_name(config);
+        let volume = redis_volume_name(config);
+
+        // redis-cli picks the password up from REDISCLI_AUTH for the health check.
+        let mut environment = HashMap::new();
+        environment.insert(
+            "REDISCLI_AUTH".to_string(),
+            format!("${{{}}}", REDIS_PASSWORD_VAR),
+        );
+
+        services.insert(
+            name.clone(),
+            sidecar_service(
+                format!("redis:{}", redis.version.as_deref().unwrap_or("7")),
+                Some(format!(
+                    "redis-server --appendonly yes --requirepass ${{{}}}",
+                    REDIS_PASSWORD_VAR
+                )),
+                Some(environment),
+                format!("{}:/data", volume),
+                "redis-cli ping | grep -q PONG".to_string(),
+                &network,
+            ),
+        );
+        volumes.insert(volume, DockerVolume::default());
+        app_environment
+            .entry("REDIS_URL".to_string())
+            .or_insert(format!(
+                "redis://:${{{}}}@{}:6379",
+                REDIS_PASSWORD_VAR, name
+            ));
+    }
+
+    networks.insert(
+        network,
+        DockerNetwork {
+            external: false,
+            internal: Some(true),
+        },
+    );
+    Ok(())
+}
+
+fn sidecar_service(
+    image: String,
+    command: Option<String>,
+    environment: Option<HashMap<String, String>>,
+    volume: String,
+    check: String,
+    network: &str,
+) -> DockerService {
+    DockerService {
+        image,
+        restart: "unless-stopped".to_string(),
+        command,
+        labels: HashMap::new(),
+        environment,
+        volumes: Some(vec![VolumeConfig::Short(volume)]),
+        networks: vec![network.to_string()],
+        healthcheck: Some(DockerHealthcheck {
+            test: vec!["CMD-SHELL".to_string(), check],
+            interval: "10s".to_string(),
+            retries: 5,
+        }),
+        mem_limit: None,
+        cpus: None,
+        pids_limit: None,
+        profiles: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SidecarConfig, SidecarsConfig};
+
+    fn config_with_sidecars() -> AppConfig {
+        AppConfig {
+            name: "myapp".to_string(),
+            services: Some(SidecarsConfig {
+                postgres: Some(SidecarConfig::default()),
+                redis: Some(SidecarConfig::default()),
+            }),
+            ..AppConfig::default()
+        }
+    }
+
+    fn temp_env_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("hobby-{}-{}.env", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn uses_an_env_file_that_already_holds_the_passwords() {
+        let file = temp_env_file("existing", "POSTGRES_PASSWORD=a\nREDIS_PASSWORD=b\n");
+        let mut config = config_with_sidecars();
+
+        ensure_passwords_in(&mut config, &file, false).unwrap();
+
+        assert_eq!(
+            config.env.as_ref().map(|env| env.file.as_str()),
+            Some(file.as_str())
+        );
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "POSTGRES_PASSWORD=a\nREDIS_PASSWORD=b\n"
+        );
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn generates_missing_passwords() {
+        let file = temp_env_file("missing", "POSTGRES_PASSWORD=a");
+        let mut config = config_with_sidecars();
+
+        ensure_passwords_in(&mut config, &file, false).unwrap();
+
+        let contents = fs::read_to_string(&file).unwrap();
+        assert!(contents.starts_with("POSTGRES_PASSWORD=a\nREDIS_PASSWORD="));
+        assert_eq!(
+            config.env.as_ref().map(|env| env.file.as_str()),
+            Some(file.as_str())
+        );
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use crate::commands::{connect_app, run_ssh_command_output};
+use crate::config::{load_app_config, AppConfig};
+use crate::terminal;
+use anyhow::Result;
+use serde::Serialize;
+use ssh2::Session;
+
+#[derive(Serialize)]
+pub struct ServiceStatus {
+    pub service: String,
+    pub state: String,
+    pub restarts: u32,
+    pub image: Option<String>,
+    pub running_version: Option<String>,
+    pub configured_version: String,
+    pub up_to_date: bool,
+    pub uptime_seconds: Option<u64>,
+    pub url: Option<String>,
+}
+
+pub fn status(json: bool) -> Result<()> {
+    let app_config = load_app_config()?;
+    let session = connect_app(&app_config)?;
+
+    let mut statuses = vec![service_status(
+        &session,
+        &app_config,
+        &app_config.name,
+        Some(&app_config.url),
+    )?];
+    for (process, process_config) in app_config.processes.iter().flatten() {
+        statuses.push(service_status(
+            &session,
+            &app_config,
+            &app_config.service_name(Some(process))?,
+            process_config.url.as_deref(),
+        )?);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+    } else {
+        print_statuses(&statuses);
+    }
+    Ok(())
+}
+
+fn service_status(
+    session: &Session,
+    config: &AppConfig,
+    service: &str,
+    url: Option<&str>,
+) -> Result<ServiceStatus> {
+    let mut status = ServiceStatus {
+        service: service.to_string(),
+        state: "not deployed".to_string(),
+        restarts: 0,
+        image: None,
+        running_version: None,
+        configured_version: config.version.clone(),
+        up_to_date: false,
+        uptime_seconds: None,
+        url: url.map(|url| format!("https://{}", url)),
+    };
+
+    let container = run_ssh_command_output(
+        session,
+        &format!(
+            "cd {} 2>/dev/null && docker compose -p {} ps -aq {} || true",
+            config.name,
+            config.project_name(),
+            service
+        ),
+    )?;
+    let container = match container.lines().next() {
+        Some(id) if !id.trim().is_empty() => id.trim().to_string(),
+        _ => return Ok(status),
+    };
+
+    let inspect = run_ssh_command_output(
+        session,
+        &format!(
+            "docker inspect --format '{{{{.State.Status}}}}\t{{{{.RestartCount}}}}\t{{{{.Config.Image}}}}\t{{{{.State.StartedAt}}}}' {}",
+            container
+        ),
+    )?;
+    let fields: Vec<&str> = inspect.trim().split('\t').collect();
+    if fields.len() != 4 {
+        anyhow::bail!("Unexpected docker inspect output: {}", inspect);
+    }
+
+    status.state = fields[0].to_string();
+    status.restarts = fields[1].parse().unwrap_or(0);
+    status.image = Some(fields[2].to_string());
+    status.running_version = fields[2].rsplit_once(':').map(|(_, tag)| tag.to_string());
+    status.up_to_date = status.running_version.as_deref() == Some(config.version.as_str());
+
+    if status.state == "running" {
+        let uptime = run_ssh_command_output(
+            session,
+            &format!("echo $(( $(date +%s) - $(date -d '{}' +%s) ))", fields[3]),
+        )?;
+        status.uptime_seconds = uptime.trim().parse().ok();
+    }
+
+    Ok(status)
+}
+
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn print_statuses(statuses: &[ServiceStatus]) {
+    let header = [
+        "SERVICE",
+        "STATE",
+        "RESTARTS",
+        "RUNNING",
+        "CONFIGURED",
+        "UPTIME",
+        "URL",
+    ];
+    let rows: Vec<[String; 7]> = statuses
+        .iter()
+        .map(|s| {
+            [
+                s.service.clone(),
+                s.state.clone(),
+                s.restarts.to_string(),
+                s.running_version.clone().unwrap_or_else(|| "-".to_string()),
+                s.configured_version.clone(),
+                s.uptime_seconds
+                    .map(format_uptime)
+                    .unwrap_or_else(|| "-".to_string()),
+                s.url.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    terminal::print_table(header, &rows);
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+pub fn stdin_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+/// Reads a line from the terminal without echoing it, for passphrases.
+pub fn prompt_hidden(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &hidden) };
+
+    let mut line = String::new();
+    let result = io::stdin().read_line(&mut line);
+
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original) };
+    println!();
+    result?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Returns the terminal size as (columns, rows), if stdout is a terminal.
+pub fn window_size() -> Option<(u32, u32)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        Some((size.ws_col as u32, size.ws_row as u32))
+    } else {
+        None
+    }
+}
+
+/// Puts the local terminal into raw mode so keystrokes like Ctrl-C reach the
+/// remote process. The previous settings are restored when dropped.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// A single-line progress display for a transfer, drawn on stderr when it
+/// is a terminal. `read` counts input consumed, `sent` the bytes on the wire.
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    read: Cell<u64>,
+    sent: Cell<u64>,
+    last_draw: Cell<Option<Instant>>,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: Option<u64>) -> Progress {
+        Progress {
+            label: label.to_string(),
+            total,
+            read: Cell::new(0),
+            sent: Cell::new(0),
+            last_draw: Cell::new(None),
+            enabled: unsafe { libc::isatty(libc::STDERR_FILENO) == 1 },
+        }
+    }
+
+    pub fn add_read(&self, bytes: usize) {
+        self.read.set(self.read.get() + bytes as u64);
+        self.draw(false);
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.set(self.sent.get() + bytes as u64);
+        self.draw(false);
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.get()
+    }
+
+    pub fn finish(&self) {
+        self.draw(true);
+        if self.enabled {
+            eprintln!();
+        }
+    }
+
+    fn draw(&self, force: bool) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last_draw.get() {
+            if !force && now.duration_since(last) < Duration::from_millis(100) {
+                return;
+            }
+        }
+        self.last_draw.set(Some(now));
+
+        let bar = match self.total {
+            Some(total) if total > 0 => {
+                let fraction = (self.read.get() as f64 / total as f64).min(1.0);
+                let filled = (fraction * 30.0) as usize;
+                format!(
+                    "[{}{}] {:>3}% ",
+                    "#".repeat(filled),
+                    " ".repeat(30 - filled),
+                    (fraction * 100.0) as u32
+                )
+            }
+            _ => String::new(),
+        };
+        eprint!(
+            "\r{}: {}{} sent",
+            self.label,
+            bar,
+            format_bytes(self.sent.get())
+        );
+        let _ = io::stderr().flush();
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Prints rows as left-aligned columns, each as wide as its widest cell.
+pub fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.iter().map(|c| c.as_str()).collect());
+    }
+}
@@ -0,0 +1,430 @@
+use crate::config::AppConfig;
+use crate::docker::{image_repository, image_tag};
+use crate::runner::Runner;
+use crate::terminal::{format_bytes, Progress};
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use tracing::info;
+
+/// Directory on the server, inside the app directory, where an image is
+/// reassembled when some of its layers are taken from images already there.
+const WORK_DIR: &str = ".hobby-image";
+
+/// The `manifest.json` entry of a `docker save` archive.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveManifest {
+    layers: Vec<String>,
+}
+
+/// An image already on the server and the layers (diff IDs) it consists of.
+struct RemoteImage {
+    id: String,
+    layers: Vec<String>,
+}
+
+/// Streams `docker save` through gzip over SSH into `docker load` on the
+/// server. When the server already has some of the layers, they are left out
+/// of the stream and the image is reassembled in [`WORK_DIR`] from the stream
+/// and the images holding them, which needs free disk about the image size.
+pub fn transfer_image(runner: &dyn Runner, config: &AppConfig) -> Result<()> {
+    let local_layers = local_layer_ids(runner, config)?;
+    let remote_images = remote_images(runner, config)?;
+    let sources = pick_sources(&local_layers, &remote_images);
+    if sources.is_empty() {
+        return stream_image(runner, config, "gunzip | docker load", &HashSet::new()).map(|_| ());
+    }
+
+    // How layers are stored in the archive depends on the Docker version and
+    // image store, manifest.json lists them in the image's layer order.
+    let layer_paths = archive_layer_paths(&image_tag(config))?;
+    if layer_paths.len() != local_layers.len() {
+        info!("Could not match archive layers to the image, sending the full image");
+        return stream_image(runner, config, "gunzip | docker load", &HashSet::new()).map(|_| ());
+    }
+
+    let reused: usize = sources.values().map(Vec::len).sum();
+    info!(
+        "Server already has {} of {} layers, sending the rest",
+        reused,
+        local_layers.len()
+    );
+
+    let result = send_missing_layers(
+        runner,
+        config,
+        &local_layers,
+        &layer_paths,
+        &sources,
+        &remote_images,
+    );
+    runner.run_remote(&[&format!("rm -rf {}/{}", config.name, WORK_DIR)])?;
+    if let Err(e) = result {
+        info!(
+            "Reassembling the image on the server failed ({}), sending the full image",
+            e
+        );
+        stream_image(runner, config, "gunzip | docker load", &HashSet::new())?;
+    }
+    Ok(())
+}
+
+/// Unpacks the stream into [`WORK_DIR`], moves the layers it left out there
+/// from the images on the server, and loads the result.
+fn send_missing_layers(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    local_layers: &[String],
+    layer_paths: &[String],
+    sources: &HashMap<String, Vec<String>>,
+    remote_images: &[RemoteImage],
+) -> Result<()> {
+    let dir = format!("{}/{}", config.name, WORK_DIR);
+
+    let reused: HashSet<&String> = sources.values().flatten().collect();
+    let mut skip: HashSet<String> = local_layers
+        .iter()
+        .zip(layer_paths)
+        .filter(|(diff_id, _)| reused.contains(diff_id))
+        .map(|(_, path)| path.clone())
+        .collect();
+    // Without the OCI index the server loads the archive through
+    // manifest.json, which does not depend on how the reused layers are stored.
+    skip.insert("index.json".to_string());
+    skip.insert("oci-layout".to_string());
+
+    let skipped = stream_image(
+        runner,
+        config,
+        &format!(
+            "rm -rf {dir} && mkdir -p {dir}/image && gunzip | tar -x -C {dir}/image",
+            dir = dir
+        ),
+        &skip,
+    )?;
+
+    for (index, (image_id, layers)) in sources.iter().enumerate() {
+        let source_layers = &remote_images
+            .iter()
+            .find(|image| &image.id == image_id)
+            .ok_or_else(|| anyhow!("Image {} disappeared from the server", image_id))?
+            .layers;
+        let source_paths = read_manifest(
+            runner
+                .query_remote(&format!("docker save {} | tar -xO manifest.json", image_id))?
+                .into_bytes(),
+        )?;
+        if source_paths.len() != source_layers.len() {
+            return Err(anyhow!("Could not match layers of image {}", image_id));
+        }
+
+        let mut moves = Vec::new();
+        for layer in layers {
+            let from = source_layers
+                .iter()
+                .position(|l| l == layer)
+                .map(|i| &source_paths[i]);
+            let to = local_layers
+                .iter()
+                .position(|l| l == layer)
+                .map(|i| &layer_paths[i])
+                .filter(|path| skipped.contains(*path));
+            if let (Some(from), Some(to)) = (from, to) {
+                moves.push((from, to));
+            }
+        }
+        if moves.is_empty() {
+            continue;
+        }
+
+        let source_dir = format!("{}/source-{}", dir, index);
+        let froms: Vec<&str> = moves.iter().map(|(from, _)| from.as_str()).collect();
+        let mut commands = vec![format!(
+            "mkdir -p {src} && docker save {} | tar -x -C {src} {}",
+            image_id,
+            froms.join(" "),
+            src = source_dir
+        )];
+        for (from, to) in moves {
+            commands.push(format!(
+                "mkdir -p $(dirname {dir}/image/{to}) && mv -f {src}/{from} {dir}/image/{to}",
+                dir = dir,
+                src = source_dir,
+                from = from,
+                to = to
+            ));
+        }
+        commands.push(format!("rm -rf {}", source_dir));
+
+        let commands: Vec<&str> = commands.iter().map(|s| s.as_str()).collect();
+        runner.run_remote(&commands)?;
+    }
+
+    runner.run_remote(&[&format!("tar -cf - -C {}/image . | docker load", dir)])
+}
+
+/// Sends the image archive to `remote_cmd`, leaving out entries in `skip`,
+/// and returns the paths that were actually left out.
+fn stream_image(
+    runner: &dyn Runner,
+    config: &AppConfig,
+    remote_cmd: &str,
+    skip: &HashSet<String>,
+) -> Result<HashSet<String>> {
+    let image = image_tag(config);
+    let progress = Progress::new("Sending image", image_size(runner, &image));
+    let mut skipped = HashSet::new();
+
+    runner.stream_to_remote(
+        &format!("docker save {} | gzip", image),
+        remote_cmd,
+        &mut |stdin| {
+            let mut child = Command::new("docker")
+                .args(["save", &image])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or_else(|| anyhow!("Failed to read docker save output"))?;
+
+            let mut reader = CountingReader {
+                inner: stdout,
+                progress: &progress,
+            };
+            let mut writer = GzEncoder::new(
+                CountingWriter {
+                    inner: stdin,
+                    progress: &progress,
+                },
+                Compression::fast(),
+            );
+            let sent = if skip.is_empty() {
+                io::copy(&mut reader, &mut writer).map(|_| ())
+            } else {
+                filter_archive(&mut reader, &mut writer, skip, &mut skipped)
+                    .and_then(|_| io::copy(&mut reader, &mut io::sink()).map(|_| ()))
+            };
+            let finished = sent.and_then(|_| writer.finish().map(|_| ()));
+            // Closing the pipe stops docker save if sending failed halfway.
+            drop(reader);
+
+            let mut stderr = String::new();
+            if let Some(mut err) = child.stderr.take() {
+                err.read_to_string(&mut stderr)?;
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(anyhow!("docker save failed: {}", stderr.trim()));
+            }
+            finished.map_err(|e| anyhow!("Failed to send image: {}", e))
+        },
+    )?;
+
+    if progress.sent() > 0 {
+        progress.finish();
+        info!("Sent {} to the server", format_bytes(progress.sent()));
+    }
+    Ok(skipped)
+}
+
+/// Copies a tar archive entry by entry, dropping the entries in `skip`.
+fn filter_archive(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    skip: &HashSet<String>,
+    skipped: &mut HashSet<String>,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let mut builder = tar::Builder::new(writer);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        if skip.contains(&path) {
+            skipped.insert(path);
+            continue;
+        }
+        let mut header = entry.header().clone();
+        builder.append_data(&mut header, &path, &mut entry)?;
+    }
+
+    builder.finish()
+}
+
+fn read_manifest(data: Vec<u8>) -> Result<Vec<String>> {
+    let manifests: Vec<ArchiveManifest> = serde_json::from_slice(&data)?;
+    manifests
+        .into_iter()
+        .next()
+        .map(|manifest| manifest.layers)
+        .ok_or_else(|| anyhow!("Image archive has an empty manifest.json"))
+}
+
+/// Paths of the layer files inside the `docker save` archive of a local
+/// image, base layer first. The archive is only read up to its manifest.
+fn archive_layer_paths(image: &str) -> Result<Vec<String>> {
+    let mut child = Command::new("docker")
+        .args(["save", image])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("Failed to read docker save output"))?;
+
+    let mut manifest = None;
+    let mut archive = tar::Archive::new(stdout);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_str() == Some("manifest.json") {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            manifest = Some(data);
+            break;
+        }
+    }
+    drop(archive);
+    let _ = child.kill();
+    child.wait()?;
+
+    read_manifest(manifest.ok_or_else(|| anyhow!("Image archive has no manifest.json"))?)
+}
+
+/// Uncompressed size of the local image, used to scale the progress bar.
+fn image_size(runner: &dyn Runner, image: &str) -> Option<u64> {
+    let output = runner
+        .run_local_output(&format!(
+            "docker image inspect --format '{{{{.Size}}}}' {}",
+            image
+        ))
+        .ok()?;
+    String::from_utf8_lossy(&output).trim().parse().ok()
+}
+
+/// Diff IDs of the local image, base layer first.
+fn local_layer_ids(runner: &dyn Runner, config: &AppConfig) -> Result<Vec<String>> {
+    let output = runner.run_local_output(&format!(
+        "docker image inspect --format '{{{{range .RootFS.Layers}}}}{{{{.}}}} {{{{end}}}}' {}",
+        image_tag(config)
+    ))?;
+    Ok(String::from_utf8_lossy(&output)
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect())
+}
+
+fn remote_images(runner: &dyn Runner, config: &AppConfig) -> Result<Vec<RemoteImage>> {
+    let output = runner.query_remote(&format!(
+        "for id in $(docker image ls {} -q | sort -u); do \
+         echo \"$id $(docker image inspect --format '{{{{range .RootFS.Layers}}}}{{{{.}}}} {{{{end}}}}' $id)\"; \
+         done",
+        image_repository(config)
+    ))?;
+
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let id = parts.next()?.to_string();
+            Some(RemoteImage {
+                id,
+                layers: parts.map(|s| s.to_string()).collect(),
+            })
+        })
+        .collect())
+}
+
+/// Picks, for every local layer the server already has, an image on the
+/// server to take it from. Images sharing the most layers are used first so
+/// as few images as possible have to be exported on the server.
+fn pick_sources(
+    local_layers: &[String],
+    remote_images: &[RemoteImage],
+) -> HashMap<String, Vec<String>> {
+    let mut images: Vec<&RemoteImage> = remote_images.iter().collect();
+    images.sort_by_key(|image| {
+        std::cmp::Reverse(
+            local_layers
+                .iter()
+                .filter(|l| image.layers.contains(l))
+                .count(),
+        )
+    });
+
+    let mut assigned: HashSet<&String> = HashSet::new();
+    let mut sources: HashMap<String, Vec<String>> = HashMap::new();
+    for image in images {
+        for layer in local_layers {
+            if !assigned.contains(layer) && image.layers.contains(layer) {
+                assigned.insert(layer);
+                sources
+                    .entry(image.id.clone())
+                    .or_default()
+                    .push(layer.clone());
+            }
+        }
+    }
+    sources
+}
+
+struct CountingReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add_read(n);
+        Ok(n)
+    }
+}
+
+struct CountingWriter<'a, W> {
+    inner: W,
+    progress: &'a Progress,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.progress.add_sent(n);
+        Ok(n)
+    }
+
+    // Channel writes are not buffered, and flushing an ssh2 channel
+    // discards data the server has sent but we have not read yet.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_layer_paths_of_every_archive_format() {
+        let legacy = br#"[{"Config":"abc.json","RepoTags":["app:V1"],"Layers":["1a2b/layer.tar","3c4d/layer.tar"]}]"#;
+        assert_eq!(
+            read_manifest(legacy.to_vec()).unwrap(),
+            vec!["1a2b/layer.tar", "3c4d/layer.tar"]
+        );
+
+        let oci = br#"[{"Config":"blobs/sha256/cf","RepoTags":["app:V1"],"Layers":["blobs/sha256/9e"],"LayerSources":{}}]"#;
+        assert_eq!(
+            read_manifest(oci.to_vec()).unwrap(),
+            vec!["blobs/sha256/9e"]
+        );
+
+        assert!(read_manifest(b"[]".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// A volume of the app's containers, in either of the compose syntaxes:
+/// `source:target[:mode]` or the long mapping form.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum VolumeConfig {
+    Short(String),
+    Long(VolumeMount),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VolumeMount {
+    /// `volume`, `bind` or `tmpfs`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Volume name or host path, left out for tmpfs and anonymous volumes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<BindOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<VolumeOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tmpfs: Option<TmpfsOptions>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BindOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub propagation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_host_path: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VolumeOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nocopy: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TmpfsOptions {
+    /// Size limit, e.g. `64m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    /// File mode of the mount as an octal number, e.g. `1777`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+const SHORT_MODES: [&str; 11] = [
+    "ro", "rw", "z", "Z", "nocopy", "shared", "slave", "private", "rshared", "rslave", "rprivate",
+];
+
+impl VolumeConfig {
+    /// Name of the named volume this mounts, which compose needs declared at
+    /// the top level. None for binds, tmpfs and anonymous volumes.
+    pub fn named_volume(&self) -> Option<&str> {
+        match self {
+            VolumeConfig::Short(spec) => {
+                let (source, _) = spec.split_once(':')?;
+                (!is_host_path(source)).then_some(source)
+            }
+            VolumeConfig::Long(mount) if mount.kind == "volume" => mount.source.as_deref(),
+            VolumeConfig::Long(_) => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            VolumeConfig::Short(spec) => validate_short(spec),
+            VolumeConfig::Long(mount) => validate_long(mount),
+        }
+        .map_err(|e| anyhow!("Invalid volume {}: {}", self.describe(), e))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            VolumeConfig::Short(spec) => format!("'{}'", spec),
+            VolumeConfig::Long(mount) => format!("with target '{}'", mount.target),
+        }
+    }
+}
+
+fn is_host_path(source: &str) -> bool {
+    source.starts_with('/') || source.starts_with('.') || source.starts_with('~')
+}
+
+fn validate_short(spec: &str) -> Result<()> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (source, target, mode) = match parts.as_slice() {
+        [target] => (None, *target, None),
+        [source, target] => (Some(*source), *target, None),
+        [source, target, mode] => (Some(*source), *target, Some(*mode)),
+        _ => return Err(anyhow!("expected source:target[:mode]")),
+    };
+
+    validate_target(target)?;
+    if let Some(source) = source {
+        if source.is_empty() {
+            return Err(anyhow!("source is empty"));
+        }
+        if !is_host_path(source) {
+            validate_volume_name(source)?;
+        }
+    }
+
+    if let Some(mode) = mode {
+        for option in mode.split(',') {
+            if !SHORT_MODES.contains(&option) {
+                return Err(anyhow!(
+                    "unknown mode '{}', expected one of {}",
+                    option,
+                    SHORT_MODES.join(", ")
+                ));
+            }
+            if option == "nocopy" && source.is_some_and(is_host_path) {
+                return Err(anyhow!("nocopy only applies to named volumes"));
+            }
+        }
+        if mode.split(',').any(|o| o == "ro") && mode.split(',').any(|o| o == "rw") {
+            return Err(anyhow!("mode cannot be both ro and rw"));
+        }
+    }
+    Ok(())
+}
+
+fn validate_long(mount: &VolumeMount) -> Result<()> {
+    validate_target(&mount.target)?;
+
+    match mount.kind.as_str() {
+        "volume" => {
+            if let Some(source) = &mount.source {
+                validate_volume_name(source)?;
+            }
+        }
+        "bind" => match &mount.source {
+            Some(source) if !source.is_empty() => {}
+            _ => return Err(anyhow!("a bind mount needs a source path")),
+        },
+        "tmpfs" => {
+            if mount.source.is_some() {
+                return Err(anyhow!("a tmpfs mount has no source"));
+            }
+        }
+        kind => {
+            return Err(anyhow!(
+                "unknown type '{}', expected volume, bind or tmpfs",
+                kind
+            ))
+        }
+    }
+
+    let options = [
+        ("bind", mount.bind.is_some()),
+        ("volume", mount.volume.is_some()),
+        ("tmpfs", mount.tmpfs.is_some()),
+    ];
+    for (kind, present) in options {
+        if present && mount.kind != kind {
+            return Err(anyhow!(
+                "`{}` options only apply to mounts of type {}",
+                kind,
+                kind
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_target(target: &str) -> Result<()> {
+    if !target.starts_with('/') {
+        return Err(anyhow!(
+            "target '{}' must be an absolute path in the container",
+            target
+        ));
+    }
+    Ok(())
+}
+
+/// Docker volume names start with a letter or digit, followed by letters,
+/// digits, `_`, `.` or `-`.
+fn validate_volume_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_start = chars.next().is_some_and(|c| c.is_ascii_alphanumeric());
+    let valid_rest = chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid_start || !valid_rest {
+        return Err(anyhow!(
+            "'{}' is neither a volume name nor a host path, host paths start with /, ./ or ~",
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn short(spec: &str) -> VolumeConfig {
+        VolumeConfig::Short(spec.to_string())
+    }
+
+    fn long(yaml: &str) -> VolumeConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn error(volume: &VolumeConfig) -> String {
+        volume.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_short_specs() {
+        for spec in [
+            "dbdata:/app/data",
+            "uploads:/app/uploads:ro",
+            "cache:/app/cache:rw,nocopy",
+            "./config:/app/config:ro",
+            "/var/log/myapp:/app/logs:rshared",
+            "~/backups:/backups",
+            "/app/tmp",
+        ] {
+            assert!(short(spec).validate().is_ok(), "{} was rejected", spec);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_short_specs() {
+        assert_eq!(
+            error(&short("data:app/data")),
+            "Invalid volume 'data:app/data': target 'app/data' must be an absolute path in the container"
+        );
+        assert_eq!(
+            error(&short("a:/b:ro:c")),
+            "Invalid volume 'a:/b:ro:c': expected source:target[:mode]"
+        );
+        assert_eq!(
+            error(&short(":/data")),
+            "Invalid volume ':/data': source is empty"
+        );
+        assert!(error(&short("data:/data:rx")).contains("unknown mode 'rx'"));
+        assert!(
+            error(&short("./data:/data:nocopy")).contains("nocopy only applies to named volumes")
+        );
+        assert!(error(&short("data:/data:ro,rw")).contains("mode cannot be both ro and rw"));
+        assert!(error(&short("my data:/data"))
+            .contains("'my data' is neither a volume name nor a host path"));
+    }
+
+    #[test]
+    fn accepts_long_specs() {
+        for yaml in [
+            "{type: volume, source: dbdata, target: /data, volume: {nocopy: true}}",
+            "{type: volume, target: /data}",
+            "{type: bind, source: ./config, target: /config, read_only: true, bind: {propagation: rshared}}",
+            "{type: tmpfs, target: /cache, tmpfs: {size: 64m, mode: 1777}}",
+        ] {
+            assert!(long(yaml).validate().is_ok(), "{} was rejected", yaml);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_long_specs() {
+        assert_eq!(
+            error(&long("{type: bind, target: /config}")),
+            "Invalid volume with target '/config': a bind mount needs a source path"
+        );
+        assert_eq!(
+            error(&long("{type: tmpfs, source: cache, target: /cache}")),
+            "Invalid volume with target '/cache': a tmpfs mount has no source"
+        );
+        assert_eq!(
+            error(&long("{type: npipe, target: /pipe}")),
+            "Invalid volume with target '/pipe': unknown type 'npipe', expected volume, bind or tmpfs"
+        );
+        assert_eq!(
+            error(&long("{type: volume, source: data, target: /data, tmpfs: {size: 64m}}")),
+            "Invalid volume with target '/data': `tmpfs` options only apply to mounts of type tmpfs"
+        );
+        assert!(error(&long("{type: volume, source: data, target: data}"))
+            .contains("must be an absolute path"));
+    }
+
+    #[test]
+    fn rejects_unknown_long_keys() {
+        let parsed: Result<VolumeConfig, _> =
+            serde_yaml::from_str("{type: volume, source: data, target: /data, readonly: true}");
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn finds_named_volumes() {
+        assert_eq!(short("dbdata:/data").named_volume(), Some("dbdata"));
+        assert_eq!(short("dbdata:/data:ro").named_volume(), Some("dbdata"));
+        assert_eq!(short("./config:/config").named_volume(), None);
+        assert_eq!(short("/data").named_volume(), None);
+        assert_eq!(
+            long("{type: volume, source: dbdata, target: /data}").named_volume(),
+            Some("dbdata")
+        );
+        assert_eq!(long("{type: volume, target: /data}").named_volume(), None);
+        assert_eq!(
+            long("{type: bind, source: ./config, target: /config}").named_volume(),
+            None
+        );
+    }
+}